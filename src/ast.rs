@@ -1,25 +1,345 @@
+use std::{collections::HashMap, fmt};
+
+use crate::lexer::Span;
+use crate::semantic_analyzer::Type;
+
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "type", content = "value"))]
 pub enum ASTNode {
     Number(f64),
-    Identifier(String),
+    Float(f64),
+    Identifier {
+        name: String,
+        span: Option<Span>,
+    },
+    UnaryOp {
+        op: UnaryOperator,
+        operand: Box<ASTNode>,
+    },
     BinaryOp {
         left: Box<ASTNode>,
         op: BinaryOperator,
         right: Box<ASTNode>,
     },
+    BuiltinCall {
+        name: String,
+        args: Vec<ASTNode>,
+    },
     Assignment {
         variable: String,
+        // Set by `x: int = ...`, which always declares a new variable
+        declared_type: Option<Type>,
+        // Set by `const x = ...`, which also always declares and can't be reassigned later
+        constant: bool,
+        // Set by `let x = ...`, which declares `x` in the current scope
+        declaration: bool,
         value: Box<ASTNode>,
+        // Position of the variable name, which the statement's instructions map back to
+        span: Option<Span>,
+    },
+    // `condition ? then_value : else_value`, which only evaluates the taken arm
+    Ternary {
+        condition: Box<ASTNode>,
+        then_value: Box<ASTNode>,
+        else_value: Box<ASTNode>,
+    },
+    // An expression evaluated only for its effects, its value is discarded
+    ExpressionStatement(Box<ASTNode>),
+    If {
+        condition: Box<ASTNode>,
+        then_branch: Box<ASTNode>,
+        else_branch: Option<Box<ASTNode>>,
+    },
+    While {
+        condition: Box<ASTNode>,
+        body: Box<ASTNode>,
+    },
+    // `break;` leaves the innermost loop, `continue;` goes back to its condition
+    Break,
+    Continue,
+    Block(Vec<ASTNode>),
+    // `read x;`, which stores a number read from the VM's input into `x`
+    Read(String),
+    // `del x;`, which removes `x` so it is undefined again
+    Delete(String),
+    // `{ a = 1; a + 2 }`, whose statements get their own scope and whose value is `result`
+    BlockExpression {
+        statements: Vec<ASTNode>,
+        result: Box<ASTNode>,
     },
     Program(Vec<ASTNode>),
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum BinaryOperator {
     Add,
     Subtract,
     Multiply,
     Divide,
+    // `//`, division rounded down to a whole number
+    FloorDiv,
+    Modulo,
+    // `^`, which is right-associative and binds tighter than unary minus
+    Power,
+    // Logical operators, which only evaluate the right side when needed
+    And,
+    Or,
+    // Comparisons evaluate to 1 when true and 0 when false
+    Equal,
+    NotEqual,
+    LessThan,
+    GreaterThan,
+    LessEqual,
+    GreaterEqual,
+}
+
+// The operator as written in source, e.g. `+` or `&&`
+impl fmt::Display for BinaryOperator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let symbol = match self {
+            BinaryOperator::Add => "+",
+            BinaryOperator::Subtract => "-",
+            BinaryOperator::Multiply => "*",
+            BinaryOperator::Divide => "/",
+            BinaryOperator::FloorDiv => "//",
+            BinaryOperator::Modulo => "%",
+            BinaryOperator::Power => "^",
+            BinaryOperator::And => "&&",
+            BinaryOperator::Or => "||",
+            BinaryOperator::Equal => "==",
+            BinaryOperator::NotEqual => "!=",
+            BinaryOperator::LessThan => "<",
+            BinaryOperator::GreaterThan => ">",
+            BinaryOperator::LessEqual => "<=",
+            BinaryOperator::GreaterEqual => ">=",
+        };
+        write!(f, "{}", symbol)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum UnaryOperator {
+    Negate,
+    // `!`, which turns 0 into 1 and anything else into 0
+    Not,
+}
+
+// Child indices from the root down to a node, following the order of child_nodes
+pub type NodePath = Vec<usize>;
+
+// Helper function to list a node's direct children in a stable order
+pub fn child_nodes(node: &ASTNode) -> Vec<&ASTNode> {
+    match node {
+        ASTNode::Number(_)
+        | ASTNode::Float(_)
+        | ASTNode::Identifier { .. }
+        | ASTNode::Read(_)
+        | ASTNode::Delete(_)
+        | ASTNode::Break
+        | ASTNode::Continue => {
+            vec![]
+        }
+        ASTNode::UnaryOp { operand, .. } => vec![operand],
+        ASTNode::BinaryOp { left, right, .. } => vec![left, right],
+        ASTNode::BuiltinCall { args, .. } => args.iter().collect(),
+        ASTNode::Assignment { value, .. } => vec![value],
+        ASTNode::Ternary {
+            condition,
+            then_value,
+            else_value,
+        } => vec![condition, then_value, else_value],
+        ASTNode::ExpressionStatement(expr) => vec![expr],
+        ASTNode::If {
+            condition,
+            then_branch,
+            else_branch,
+        } => {
+            let mut children: Vec<&ASTNode> = vec![condition, then_branch];
+            children.extend(else_branch.as_deref());
+            children
+        }
+        ASTNode::While { condition, body } => vec![condition, body],
+        ASTNode::Block(statements) | ASTNode::Program(statements) => statements.iter().collect(),
+        ASTNode::BlockExpression { statements, result } => {
+            let mut children: Vec<&ASTNode> = statements.iter().collect();
+            children.push(result);
+            children
+        }
+    }
+}
+
+// Size and shape of a tree, for rejecting oversized programs before compiling them
+#[derive(Debug, Default, PartialEq)]
+pub struct AstStats {
+    pub node_count: usize,
+    // A lone node has depth 1
+    pub max_depth: usize,
+    // Nodes of each variant, keyed by variant name, e.g. "BinaryOp"
+    pub variant_counts: HashMap<&'static str, usize>,
+}
+
+pub fn ast_stats(node: &ASTNode) -> AstStats {
+    let mut stats = AstStats::default();
+    collect_stats(node, 1, &mut stats);
+    stats
+}
+
+fn collect_stats(node: &ASTNode, depth: usize, stats: &mut AstStats) {
+    stats.node_count += 1;
+    stats.max_depth = stats.max_depth.max(depth);
+    *stats.variant_counts.entry(variant_name(node)).or_insert(0) += 1;
+    for child in child_nodes(node) {
+        collect_stats(child, depth + 1, stats);
+    }
+}
+
+pub fn variant_name(node: &ASTNode) -> &'static str {
+    match node {
+        ASTNode::Number(_) => "Number",
+        ASTNode::Float(_) => "Float",
+        ASTNode::Identifier { .. } => "Identifier",
+        ASTNode::UnaryOp { .. } => "UnaryOp",
+        ASTNode::BinaryOp { .. } => "BinaryOp",
+        ASTNode::BuiltinCall { .. } => "BuiltinCall",
+        ASTNode::Assignment { .. } => "Assignment",
+        ASTNode::Ternary { .. } => "Ternary",
+        ASTNode::ExpressionStatement(_) => "ExpressionStatement",
+        ASTNode::If { .. } => "If",
+        ASTNode::While { .. } => "While",
+        ASTNode::Break => "Break",
+        ASTNode::Continue => "Continue",
+        ASTNode::Block(_) => "Block",
+        ASTNode::Read(_) => "Read",
+        ASTNode::Delete(_) => "Delete",
+        ASTNode::BlockExpression { .. } => "BlockExpression",
+        ASTNode::Program(_) => "Program",
+    }
+}
+
+// Helper function to find the node at `path` below `root`
+pub fn node_at<'a>(root: &'a ASTNode, path: &[usize]) -> Option<&'a ASTNode> {
+    path.iter()
+        .try_fold(root, |node, index| child_nodes(node).get(*index).copied())
+}
+
+// Traversal over the AST. Every method defaults to visiting the node's children,
+// so a pass only needs to override the variants it cares about.
+pub trait Visitor {
+    fn visit_number(&mut self, _value: f64) {}
+
+    fn visit_identifier(&mut self, _name: &str) {}
+
+    fn visit_unary_op(&mut self, _op: &UnaryOperator, operand: &ASTNode) {
+        walk(self, operand);
+    }
+
+    fn visit_binary_op(&mut self, left: &ASTNode, _op: &BinaryOperator, right: &ASTNode) {
+        walk(self, left);
+        walk(self, right);
+    }
+
+    fn visit_builtin_call(&mut self, _name: &str, args: &[ASTNode]) {
+        for arg in args {
+            walk(self, arg);
+        }
+    }
+
+    fn visit_assignment(&mut self, _variable: &str, value: &ASTNode) {
+        walk(self, value);
+    }
+
+    fn visit_ternary(&mut self, condition: &ASTNode, then_value: &ASTNode, else_value: &ASTNode) {
+        walk(self, condition);
+        walk(self, then_value);
+        walk(self, else_value);
+    }
+
+    fn visit_expression_statement(&mut self, expr: &ASTNode) {
+        walk(self, expr);
+    }
+
+    fn visit_if(
+        &mut self,
+        condition: &ASTNode,
+        then_branch: &ASTNode,
+        else_branch: Option<&ASTNode>,
+    ) {
+        walk(self, condition);
+        walk(self, then_branch);
+        if let Some(else_branch) = else_branch {
+            walk(self, else_branch);
+        }
+    }
+
+    fn visit_while(&mut self, condition: &ASTNode, body: &ASTNode) {
+        walk(self, condition);
+        walk(self, body);
+    }
+
+    fn visit_break(&mut self) {}
+
+    fn visit_continue(&mut self) {}
+
+    fn visit_block(&mut self, statements: &[ASTNode]) {
+        for statement in statements {
+            walk(self, statement);
+        }
+    }
+
+    fn visit_read(&mut self, _variable: &str) {}
+
+    fn visit_delete(&mut self, _variable: &str) {}
+
+    fn visit_block_expression(&mut self, statements: &[ASTNode], result: &ASTNode) {
+        for statement in statements {
+            walk(self, statement);
+        }
+        walk(self, result);
+    }
+
+    fn visit_program(&mut self, statements: &[ASTNode]) {
+        for statement in statements {
+            walk(self, statement);
+        }
+    }
+}
+
+// Dispatches a node to the matching Visitor method
+pub fn walk<V: Visitor + ?Sized>(visitor: &mut V, node: &ASTNode) {
+    match node {
+        ASTNode::Number(x) | ASTNode::Float(x) => visitor.visit_number(*x),
+        ASTNode::Identifier { name, .. } => visitor.visit_identifier(name),
+        ASTNode::UnaryOp { op, operand } => visitor.visit_unary_op(op, operand),
+        ASTNode::BinaryOp { left, op, right } => visitor.visit_binary_op(left, op, right),
+        ASTNode::BuiltinCall { name, args } => visitor.visit_builtin_call(name, args),
+        ASTNode::Assignment {
+            variable, value, ..
+        } => visitor.visit_assignment(variable, value),
+        ASTNode::Ternary {
+            condition,
+            then_value,
+            else_value,
+        } => visitor.visit_ternary(condition, then_value, else_value),
+        ASTNode::ExpressionStatement(expr) => visitor.visit_expression_statement(expr),
+        ASTNode::If {
+            condition,
+            then_branch,
+            else_branch,
+        } => visitor.visit_if(condition, then_branch, else_branch.as_deref()),
+        ASTNode::While { condition, body } => visitor.visit_while(condition, body),
+        ASTNode::Block(statements) => visitor.visit_block(statements),
+        ASTNode::Read(variable) => visitor.visit_read(variable),
+        ASTNode::Delete(variable) => visitor.visit_delete(variable),
+        ASTNode::Break => visitor.visit_break(),
+        ASTNode::Continue => visitor.visit_continue(),
+        ASTNode::BlockExpression { statements, result } => {
+            visitor.visit_block_expression(statements, result)
+        }
+        ASTNode::Program(statements) => visitor.visit_program(statements),
+    }
 }
 
 // Helper function to create boxed AST nodes
@@ -27,6 +347,318 @@ pub fn boxed_node(node: ASTNode) -> Box<ASTNode> {
     Box::new(node)
 }
 
+// The source position recorded on a node, if the parser gave it one
+pub fn node_span(node: &ASTNode) -> Option<Span> {
+    match node {
+        ASTNode::Identifier { span, .. } | ASTNode::Assignment { span, .. } => *span,
+        _ => None,
+    }
+}
+
+// Helper function to copy a tree with every span cleared, for comparing structure only
+pub fn strip_spans(node: &ASTNode) -> ASTNode {
+    let strip = |x: &ASTNode| boxed_node(strip_spans(x));
+    match node {
+        ASTNode::Identifier { name, .. } => ASTNode::Identifier {
+            name: name.clone(),
+            span: None,
+        },
+        ASTNode::UnaryOp { op, operand } => ASTNode::UnaryOp {
+            op: op.clone(),
+            operand: strip(operand),
+        },
+        ASTNode::BinaryOp { left, op, right } => ASTNode::BinaryOp {
+            left: strip(left),
+            op: op.clone(),
+            right: strip(right),
+        },
+        ASTNode::BuiltinCall { name, args } => ASTNode::BuiltinCall {
+            name: name.clone(),
+            args: args.iter().map(strip_spans).collect(),
+        },
+        ASTNode::Assignment {
+            variable,
+            declared_type,
+            constant,
+            declaration,
+            value,
+            ..
+        } => ASTNode::Assignment {
+            variable: variable.clone(),
+            declared_type: declared_type.clone(),
+            constant: *constant,
+            declaration: *declaration,
+            value: strip(value),
+            span: None,
+        },
+        ASTNode::Ternary {
+            condition,
+            then_value,
+            else_value,
+        } => ASTNode::Ternary {
+            condition: strip(condition),
+            then_value: strip(then_value),
+            else_value: strip(else_value),
+        },
+        ASTNode::ExpressionStatement(expr) => ASTNode::ExpressionStatement(strip(expr)),
+        ASTNode::If {
+            condition,
+            then_branch,
+            else_branch,
+        } => ASTNode::If {
+            condition: strip(condition),
+            then_branch: strip(then_branch),
+            else_branch: else_branch.as_deref().map(strip),
+        },
+        ASTNode::While { condition, body } => ASTNode::While {
+            condition: strip(condition),
+            body: strip(body),
+        },
+        ASTNode::Block(statements) => ASTNode::Block(statements.iter().map(strip_spans).collect()),
+        ASTNode::BlockExpression { statements, result } => ASTNode::BlockExpression {
+            statements: statements.iter().map(strip_spans).collect(),
+            result: strip(result),
+        },
+        ASTNode::Program(statements) => {
+            ASTNode::Program(statements.iter().map(strip_spans).collect())
+        }
+        ASTNode::Number(_)
+        | ASTNode::Float(_)
+        | ASTNode::Read(_)
+        | ASTNode::Delete(_)
+        | ASTNode::Break
+        | ASTNode::Continue => node.clone(),
+    }
+}
+
+// Helper function to fold an expression made up only of literals into its value.
+// Returns None if the expression reads a variable or would divide by zero.
+pub fn evaluate_constant(node: &ASTNode) -> Option<f64> {
+    match node {
+        ASTNode::Number(x) | ASTNode::Float(x) => Some(*x),
+        ASTNode::UnaryOp {
+            op: UnaryOperator::Negate,
+            operand,
+        } => evaluate_constant(operand).map(|x| -x),
+        ASTNode::UnaryOp {
+            op: UnaryOperator::Not,
+            operand,
+        } => evaluate_constant(operand).map(|x| f64::from(x == 0.0)),
+        ASTNode::BinaryOp { left, op, right } => {
+            let left = evaluate_constant(left)?;
+            let right = evaluate_constant(right)?;
+            match op {
+                BinaryOperator::Add => Some(left + right),
+                BinaryOperator::Subtract => Some(left - right),
+                BinaryOperator::Multiply => Some(left * right),
+                BinaryOperator::Divide if right != 0.0 => Some(left / right),
+                BinaryOperator::FloorDiv if right != 0.0 => Some((left / right).floor()),
+                BinaryOperator::Modulo if right != 0.0 => Some(left % right),
+                BinaryOperator::Power => Some(left.powf(right)),
+                BinaryOperator::And => Some(f64::from(left != 0.0 && right != 0.0)),
+                BinaryOperator::Or => Some(f64::from(left != 0.0 || right != 0.0)),
+                BinaryOperator::Equal => Some(f64::from(left == right)),
+                BinaryOperator::NotEqual => Some(f64::from(left != right)),
+                BinaryOperator::LessThan => Some(f64::from(left < right)),
+                BinaryOperator::GreaterThan => Some(f64::from(left > right)),
+                BinaryOperator::LessEqual => Some(f64::from(left <= right)),
+                BinaryOperator::GreaterEqual => Some(f64::from(left >= right)),
+                _ => None,
+            }
+        }
+        ASTNode::Ternary {
+            condition,
+            then_value,
+            else_value,
+        } => match evaluate_constant(condition)? {
+            x if x != 0.0 => evaluate_constant(then_value),
+            _ => evaluate_constant(else_value),
+        },
+        _ => None,
+    }
+}
+
+// Helper function to print an expression in reverse Polish notation, e.g. `10 5 2 * +`.
+// Operands come before their operator, the same order the interpreter emits them in.
+// Block expressions and statements have no such form and are an error.
+pub fn to_rpn(node: &ASTNode) -> Result<String, String> {
+    Ok(match node {
+        ASTNode::Number(x) | ASTNode::Float(x) => x.to_string(),
+        ASTNode::Identifier { name, .. } => name.clone(),
+        ASTNode::UnaryOp {
+            op: UnaryOperator::Negate,
+            operand,
+        } => format!("{} neg", to_rpn(operand)?),
+        ASTNode::UnaryOp {
+            op: UnaryOperator::Not,
+            operand,
+        } => format!("{} !", to_rpn(operand)?),
+        ASTNode::BinaryOp { left, op, right } => {
+            format!("{} {} {}", to_rpn(left)?, to_rpn(right)?, op)
+        }
+        ASTNode::BuiltinCall { name, args } => {
+            let mut parts = args.iter().map(to_rpn).collect::<Result<Vec<_>, _>>()?;
+            parts.push(name.clone());
+            parts.join(" ")
+        }
+        ASTNode::Ternary {
+            condition,
+            then_value,
+            else_value,
+        } => format!(
+            "{} {} {} ?:",
+            to_rpn(condition)?,
+            to_rpn(then_value)?,
+            to_rpn(else_value)?
+        ),
+        ASTNode::ExpressionStatement(expr) => to_rpn(expr)?,
+        other => {
+            return Err(format!(
+                "{} has no reverse Polish form",
+                variant_name(other)
+            ))
+        }
+    })
+}
+
+// Helper function to render a node as an indented tree for debugging, e.g.
+//
+// BinaryOp(Add)
+// ├── left: Number(1)
+// └── right: Identifier(x)
+pub fn print_tree(node: &ASTNode) -> String {
+    let mut out = tree_label(node);
+    write_tree_children(node, "", &mut out);
+    out
+}
+
+fn write_tree_children(node: &ASTNode, prefix: &str, out: &mut String) {
+    let children = tree_children(node);
+    let count = children.len();
+    for (index, (role, child)) in children.into_iter().enumerate() {
+        let last = index + 1 == count;
+        out.push('\n');
+        out.push_str(prefix);
+        out.push_str(if last { "└── " } else { "├── " });
+        if let Some(role) = role {
+            out.push_str(role);
+            out.push_str(": ");
+        }
+        out.push_str(&tree_label(child));
+        let child_prefix = format!("{}{}", prefix, if last { "    " } else { "│   " });
+        write_tree_children(child, &child_prefix, out);
+    }
+}
+
+// Helper function to describe a node as a Graphviz digraph, for `dot -Tpng`. Nodes
+// are numbered in preorder and edges to named children carry the child's role.
+pub fn to_dot(node: &ASTNode) -> String {
+    let mut lines = vec!["digraph AST {".to_string()];
+    write_dot_node(node, &mut 0, &mut lines);
+    lines.push("}".to_string());
+    lines.join("\n")
+}
+
+// Returns the id given to `node`
+fn write_dot_node(node: &ASTNode, next_id: &mut usize, lines: &mut Vec<String>) -> usize {
+    let id = *next_id;
+    *next_id += 1;
+    lines.push(format!(
+        "    n{} [label=\"{}\"];",
+        id,
+        tree_label(node).replace('"', "\\\"")
+    ));
+
+    for (role, child) in tree_children(node) {
+        let child_id = write_dot_node(child, next_id, lines);
+        lines.push(match role {
+            Some(role) => format!("    n{} -> n{} [label=\"{}\"];", id, child_id, role),
+            None => format!("    n{} -> n{};", id, child_id),
+        });
+    }
+    id
+}
+
+fn tree_label(node: &ASTNode) -> String {
+    match node {
+        ASTNode::Number(x) => format!("Number({})", x),
+        ASTNode::Float(x) => format!("Float({})", x),
+        ASTNode::Identifier { name, .. } => format!("Identifier({})", name),
+        ASTNode::UnaryOp { op, .. } => format!("UnaryOp({:?})", op),
+        ASTNode::BinaryOp { op, .. } => format!("BinaryOp({:?})", op),
+        ASTNode::BuiltinCall { name, .. } => format!("BuiltinCall({})", name),
+        ASTNode::Assignment {
+            variable,
+            declared_type,
+            constant,
+            declaration,
+            ..
+        } => {
+            let constant = match (*constant, *declaration) {
+                (true, _) => "const ",
+                (false, true) => "let ",
+                (false, false) => "",
+            };
+            match declared_type {
+                Some(declared_type) => {
+                    format!("Assignment({}{}: {:?})", constant, variable, declared_type)
+                }
+                None => format!("Assignment({}{})", constant, variable),
+            }
+        }
+        ASTNode::Read(variable) => format!("Read({})", variable),
+        ASTNode::Delete(variable) => format!("Delete({})", variable),
+        other => variant_name(other).to_string(),
+    }
+}
+
+// Children in child_nodes order, named where the node has fixed fields
+fn tree_children(node: &ASTNode) -> Vec<(Option<&'static str>, &ASTNode)> {
+    match node {
+        ASTNode::UnaryOp { operand, .. } => vec![(Some("operand"), operand)],
+        ASTNode::BinaryOp { left, right, .. } => vec![(Some("left"), left), (Some("right"), right)],
+        ASTNode::Assignment { value, .. } => vec![(Some("value"), value)],
+        ASTNode::Ternary {
+            condition,
+            then_value,
+            else_value,
+        } => vec![
+            (Some("condition"), condition),
+            (Some("then"), then_value),
+            (Some("else"), else_value),
+        ],
+        ASTNode::If {
+            condition,
+            then_branch,
+            else_branch,
+        } => {
+            let mut children = vec![
+                (Some("condition"), &**condition),
+                (Some("then"), then_branch),
+            ];
+            children.extend(else_branch.as_deref().map(|x| (Some("else"), x)));
+            children
+        }
+        ASTNode::While { condition, body } => {
+            vec![(Some("condition"), condition), (Some("body"), body)]
+        }
+        ASTNode::BlockExpression { statements, result } => {
+            let mut children: Vec<_> = statements.iter().map(|x| (None, x)).collect();
+            children.push((Some("result"), result));
+            children
+        }
+        _ => child_nodes(node).into_iter().map(|x| (None, x)).collect(),
+    }
+}
+
+// Helper function to serialize a node for external tools. Every node is tagged
+// with its variant, e.g. `{"type":"Number","value":5.0}`, and struct variants
+// keep their field names: `{"type":"BinaryOp","value":{"left":..,"op":"Add",..}}`
+#[cfg(feature = "serde")]
+pub fn to_json(node: &ASTNode) -> String {
+    serde_json::to_string(node).expect("an ASTNode always serializes")
+}
+
 // Helper function to convert Token to BinaryOperator
 pub fn token_to_binary_op(token: crate::lexer::Token) -> Result<BinaryOperator, String> {
     use crate::lexer::Token;
@@ -35,6 +667,17 @@ pub fn token_to_binary_op(token: crate::lexer::Token) -> Result<BinaryOperator,
         Token::Minus => Ok(BinaryOperator::Subtract),
         Token::Multiply => Ok(BinaryOperator::Multiply),
         Token::Divide => Ok(BinaryOperator::Divide),
+        Token::DoubleSlash => Ok(BinaryOperator::FloorDiv),
+        Token::Modulo => Ok(BinaryOperator::Modulo),
+        Token::Caret => Ok(BinaryOperator::Power),
+        Token::And => Ok(BinaryOperator::And),
+        Token::Or => Ok(BinaryOperator::Or),
+        Token::Equal => Ok(BinaryOperator::Equal),
+        Token::NotEqual => Ok(BinaryOperator::NotEqual),
+        Token::Less => Ok(BinaryOperator::LessThan),
+        Token::Greater => Ok(BinaryOperator::GreaterThan),
+        Token::LessEqual => Ok(BinaryOperator::LessEqual),
+        Token::GreaterEqual => Ok(BinaryOperator::GreaterEqual),
         other => Err(format!("Token {:?} was not a binary operator", other)),
     }
 }
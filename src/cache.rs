@@ -0,0 +1,62 @@
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash, Hasher},
+};
+
+use crate::{compile, CompiledProgram, Instruction, RunError, VM};
+
+// Compiled programs keyed by a hash of their source, for embedders that run the
+// same scripts repeatedly. The source is kept too, so a hash collision recompiles
+// rather than running the wrong program.
+#[derive(Default)]
+pub struct ProgramCache {
+    programs: HashMap<u64, (String, CompiledProgram)>,
+    compile_count: usize,
+}
+
+impl ProgramCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get_or_compile(&mut self, source: &str) -> Result<&[Instruction], RunError> {
+        Ok(&self.program(source)?.instructions)
+    }
+
+    // Like crate::run, but compiling `source` only the first time it is seen
+    pub fn run(&mut self, source: &str) -> Result<HashMap<String, f64>, RunError> {
+        let program = self.program(source)?.clone();
+        let mut vm = VM::new(
+            program.instructions,
+            program.constants,
+            program.symbol_table,
+        );
+        vm.execute().map_err(RunError::Vm)
+    }
+
+    // How many times a source had to be compiled, failed compilations included
+    pub fn compile_count(&self) -> usize {
+        self.compile_count
+    }
+
+    pub fn clear(&mut self) {
+        self.programs.clear();
+    }
+
+    fn program(&mut self, source: &str) -> Result<&CompiledProgram, RunError> {
+        let mut hasher = DefaultHasher::new();
+        source.hash(&mut hasher);
+        let key = hasher.finish();
+
+        let cached = self
+            .programs
+            .get(&key)
+            .is_some_and(|(cached_source, _)| cached_source == source);
+        if !cached {
+            self.compile_count += 1;
+            let program = compile(source)?;
+            self.programs.insert(key, (source.to_string(), program));
+        }
+        Ok(&self.programs[&key].1)
+    }
+}
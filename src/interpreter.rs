@@ -1,62 +1,355 @@
-use crate::ast::{ASTNode, BinaryOperator};
+use std::fmt;
 
-#[derive(Debug, Clone)]
+use crate::ast::{
+    child_nodes, node_span, walk, ASTNode, BinaryOperator, NodePath, UnaryOperator, Visitor,
+};
+use crate::lexer::Span;
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum Instruction {
-    LoadConstant(f64),
+    // Index into the constant pool
+    LoadConstant(usize),
     LoadVariable(String),
     StoreVariable(String),
+    // Reads a number from the VM's input and stores it, pushing nothing
+    ReadInput(String),
+    // Removes a variable from storage, so loading it again fails
+    DeleteVariable(String),
+    // Opens a block expression's scope, saving the listed names it declares so
+    // ExitScope can put back what they shadowed. Names first stored inside the
+    // scope are removed when it exits.
+    EnterScope(Vec<String>),
+    ExitScope,
     Add,
     Subtract,
     Divide,
+    FloorDivide,
     Multiply,
+    Modulo,
+    Negate,
+    Not,
+    // Stack primitives
+    Dup,
+    Pop,
+    // Builtin functions, reading their arguments from the stack
+    Sqrt,
+    Abs,
+    PowBuiltin,
+    // Follow f64::min and f64::max, so a NaN argument yields the other one
+    Min,
+    Max,
+    // Computed by crate::math, following the VM's MathMode
+    Sin,
+    Cos,
+    Exp,
+    Ln,
+    // Writes the top of the stack to the VM's output, leaving it in place
+    Print,
+    Equal,
+    NotEqual,
+    LessThan,
+    GreaterThan,
+    LessEqual,
+    GreaterEqual,
+
+    // Control flow, targets are instruction indices
+    Jump(usize),
+    JumpIfFalse(usize),
+
+    // Calls push a frame and jump to the target, Return resumes after the call
+    Call(usize),
+    Return,
 
     // End program
     Stop,
 }
 
+// Names that `let`, `const` and typed assignments declare in the scope `node` is in,
+// which doesn't include those inside a nested block expression
+fn collect_declarations(node: &ASTNode, declared: &mut Vec<String>) {
+    match node {
+        ASTNode::BlockExpression { .. } => return,
+        ASTNode::Assignment {
+            variable,
+            declared_type,
+            constant,
+            declaration,
+            ..
+        } if (declared_type.is_some() || *constant || *declaration)
+            && !declared.contains(variable) =>
+        {
+            declared.push(variable.clone());
+        }
+        _ => {}
+    }
+    for child in child_nodes(node) {
+        collect_declarations(child, declared);
+    }
+}
+
+// Assembly-style listing, e.g. `LOAD_CONST 5` or `STORE x`
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Instruction::LoadConstant(index) => write!(f, "LOAD_CONST #{}", index),
+            Instruction::LoadVariable(name) => write!(f, "LOAD {}", name),
+            Instruction::StoreVariable(name) => write!(f, "STORE {}", name),
+            Instruction::ReadInput(name) => write!(f, "READ {}", name),
+            Instruction::DeleteVariable(name) => write!(f, "DEL {}", name),
+            Instruction::EnterScope(names) => write!(f, "ENTER_SCOPE {}", names.join(", ")),
+            Instruction::ExitScope => write!(f, "EXIT_SCOPE"),
+            Instruction::Add => write!(f, "ADD"),
+            Instruction::Subtract => write!(f, "SUB"),
+            Instruction::Divide => write!(f, "DIV"),
+            Instruction::FloorDivide => write!(f, "FLOOR_DIV"),
+            Instruction::Multiply => write!(f, "MUL"),
+            Instruction::Modulo => write!(f, "MOD"),
+            Instruction::Negate => write!(f, "NEG"),
+            Instruction::Not => write!(f, "NOT"),
+            Instruction::Dup => write!(f, "DUP"),
+            Instruction::Pop => write!(f, "POP"),
+            Instruction::Sqrt => write!(f, "SQRT"),
+            Instruction::Abs => write!(f, "ABS"),
+            Instruction::PowBuiltin => write!(f, "POW"),
+            Instruction::Min => write!(f, "MIN"),
+            Instruction::Max => write!(f, "MAX"),
+            Instruction::Sin => write!(f, "SIN"),
+            Instruction::Cos => write!(f, "COS"),
+            Instruction::Exp => write!(f, "EXP"),
+            Instruction::Ln => write!(f, "LN"),
+            Instruction::Print => write!(f, "PRINT"),
+            Instruction::Equal => write!(f, "EQ"),
+            Instruction::NotEqual => write!(f, "NE"),
+            Instruction::LessThan => write!(f, "LT"),
+            Instruction::GreaterThan => write!(f, "GT"),
+            Instruction::LessEqual => write!(f, "LE"),
+            Instruction::GreaterEqual => write!(f, "GE"),
+            Instruction::Jump(target) => write!(f, "JUMP {}", target),
+            Instruction::JumpIfFalse(target) => write!(f, "JUMP_IF_FALSE {}", target),
+            Instruction::Call(target) => write!(f, "CALL {}", target),
+            Instruction::Return => write!(f, "RET"),
+            Instruction::Stop => write!(f, "STOP"),
+        }
+    }
+}
+
+impl Instruction {
+    // The instruction index this one may transfer control to
+    pub fn target(&self) -> Option<usize> {
+        match self {
+            Instruction::Jump(target)
+            | Instruction::JumpIfFalse(target)
+            | Instruction::Call(target) => Some(*target),
+            _ => None,
+        }
+    }
+}
+
+// Listing with each jump target replaced by a label, numbered in address order:
+//
+// LABEL_0:
+//     LOAD i
+//     JUMP_IF_FALSE LABEL_1
+//     JUMP LABEL_0
+// LABEL_1:
+//     STOP
+pub fn disassemble(instructions: &[Instruction]) -> String {
+    let mut targets: Vec<usize> = instructions
+        .iter()
+        .filter_map(Instruction::target)
+        .collect();
+    targets.sort_unstable();
+    targets.dedup();
+    let label = |target: usize| match targets.binary_search(&target) {
+        Ok(index) => format!("LABEL_{}", index),
+        Err(_) => target.to_string(),
+    };
+
+    let mut lines = vec![];
+    for (index, instruction) in instructions.iter().enumerate() {
+        if targets.binary_search(&index).is_ok() {
+            lines.push(format!("{}:", label(index)));
+        }
+        let text = match instruction {
+            Instruction::Jump(target) => format!("JUMP {}", label(*target)),
+            Instruction::JumpIfFalse(target) => format!("JUMP_IF_FALSE {}", label(*target)),
+            Instruction::Call(target) => format!("CALL {}", label(*target)),
+            other => other.to_string(),
+        };
+        lines.push(format!("    {}", text));
+    }
+    // A jump may target the end of the program
+    if targets.binary_search(&instructions.len()).is_ok() {
+        lines.push(format!("{}:", label(instructions.len())));
+    }
+    lines.join("\n")
+}
+
+// Choices that change what the generated code computes
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct CompileOptions {
+    // Compile `/` like `//`, so `7 / 2` is 3 rather than 3.5
+    pub integer_division: bool,
+}
+
+// Where `continue` jumps back to, and the `break` jumps waiting for the loop's end
+struct LoopContext {
+    start: usize,
+    breaks: Vec<usize>,
+}
+
 pub struct Interpreter {
+    options: CompileOptions,
     instructions: Vec<Instruction>,
+    // Every distinct literal, referenced by index from LoadConstant
+    constants: Vec<f64>,
+    // Path of the node that produced each instruction, parallel to `instructions`
+    provenance: Vec<NodePath>,
+    current_path: NodePath,
+    // Source position of each instruction, taken from the nearest enclosing node with a span
+    line_table: Vec<Option<Span>>,
+    current_span: Option<Span>,
+    // Loops being generated, innermost last
+    loops: Vec<LoopContext>,
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Interpreter {
     pub fn new() -> Interpreter {
+        Self::with_options(CompileOptions::default())
+    }
+
+    pub fn with_options(options: CompileOptions) -> Interpreter {
         let operations: Vec<Instruction> = vec![];
         Interpreter {
+            options,
             instructions: operations,
+            constants: vec![],
+            provenance: vec![],
+            current_path: vec![],
+            line_table: vec![],
+            current_span: None,
+            loops: vec![],
         }
     }
 
-    pub fn generate_instructions(&mut self, program: &ASTNode) -> Vec<Instruction> {
+    // Returns the instructions along with the constant pool they index into. A
+    // bare expression instead of a Program leaves its value on the stack at Stop
+    pub fn generate_instructions(&mut self, program: &ASTNode) -> (Vec<Instruction>, Vec<f64>) {
+        let instructions = self.generate_with_provenance(program).0;
+        (instructions, self.constants.clone())
+    }
+
+    // Constant pool of the most recently generated program
+    pub fn constants(&self) -> &[f64] {
+        &self.constants
+    }
+
+    // Also returns, for each instruction, the path of the AST node that produced it
+    pub fn generate_with_provenance(
+        &mut self,
+        program: &ASTNode,
+    ) -> (Vec<Instruction>, Vec<NodePath>) {
         self.instructions.clear();
-        self.visit_node(program);
-        self.instructions.push(Instruction::Stop);
-        self.instructions.clone()
-    }
-
-    fn visit_node(&mut self, node: &ASTNode) {
-        let instruction = match node {
-            ASTNode::Number(x) => Some(Instruction::LoadConstant(x.clone())),
-            ASTNode::Identifier(x) => Some(Instruction::LoadVariable(x.clone())),
-            ASTNode::BinaryOp { left, op, right } => {
-                self.visit_node(left);
-                self.visit_node(right);
-                Some(self.binary_op_to_instruction(op))
-            }
-            ASTNode::Assignment { variable, value } => {
-                self.visit_node(value);
-                Some(Instruction::StoreVariable(variable.to_string()))
-            }
-            ASTNode::Program(program) => {
-                for node in program {
-                    self.visit_node(node);
-                }
-                None
+        self.constants.clear();
+        self.provenance.clear();
+        self.current_path.clear();
+        self.line_table.clear();
+        self.current_span = None;
+        self.loops.clear();
+
+        walk(self, program);
+        self.emit(Instruction::Stop);
+
+        (self.instructions.clone(), self.provenance.clone())
+    }
+
+    // Also returns the source position of each instruction, for VM::set_line_table
+    pub fn generate_with_line_table(
+        &mut self,
+        program: &ASTNode,
+    ) -> (Vec<Instruction>, Vec<Option<Span>>) {
+        let instructions = self.generate_with_provenance(program).0;
+        (instructions, self.line_table.clone())
+    }
+
+    fn emit(&mut self, instruction: Instruction) {
+        self.instructions.push(instruction);
+        self.provenance.push(self.current_path.clone());
+        self.line_table.push(self.current_span);
+    }
+
+    // Loads `value` through the pool, reusing the entry of an identical earlier constant
+    fn emit_constant(&mut self, value: f64) {
+        let index = match self
+            .constants
+            .iter()
+            .position(|x| x.to_bits() == value.to_bits())
+        {
+            Some(index) => index,
+            None => {
+                self.constants.push(value);
+                self.constants.len() - 1
             }
         };
+        self.emit(Instruction::LoadConstant(index));
+    }
+
+    fn walk_child(&mut self, index: usize, node: &ASTNode) {
+        let outer_span = self.current_span;
+        self.current_span = node_span(node).or(outer_span);
+        self.current_path.push(index);
+        walk(self, node);
+        self.current_path.pop();
+        self.current_span = outer_span;
+    }
+
+    // Emits a jump with a placeholder target and returns its index for patching
+    fn emit_jump(&mut self, jump: Instruction) -> usize {
+        self.emit(jump);
+        self.instructions.len() - 1
+    }
+
+    // Points the jump at `index` to the next instruction to be emitted
+    fn patch_jump(&mut self, index: usize) {
+        let target = self.instructions.len();
+        match &mut self.instructions[index] {
+            Instruction::Jump(x) | Instruction::JumpIfFalse(x) => *x = target,
+            other => panic!("Cannot patch a non-jump instruction! ({:?})", other),
+        }
+    }
+
+    // Short-circuits with jumps, leaving 1 or 0 on the stack. `||` only evaluates
+    // the right side if the left is false, `&&` only if the left is true.
+    fn emit_logical(&mut self, left: &ASTNode, op: &BinaryOperator, right: &ASTNode) {
+        self.walk_child(0, left);
+        let left_false = self.emit_jump(Instruction::JumpIfFalse(0));
+
+        let mut to_true = vec![];
+        if *op == BinaryOperator::Or {
+            to_true.push(self.emit_jump(Instruction::Jump(0)));
+            self.patch_jump(left_false);
+        }
 
-        if let Some(x) = instruction {
-            self.instructions.push(x);
+        self.walk_child(1, right);
+        let right_false = self.emit_jump(Instruction::JumpIfFalse(0));
+        for jump in to_true {
+            self.patch_jump(jump);
         }
+        self.emit_constant(1.0);
+        let to_end = self.emit_jump(Instruction::Jump(0));
+
+        if *op == BinaryOperator::And {
+            self.patch_jump(left_false);
+        }
+        self.patch_jump(right_false);
+        self.emit_constant(0.0);
+        self.patch_jump(to_end);
     }
 
     fn binary_op_to_instruction(&self, op: &BinaryOperator) -> Instruction {
@@ -64,7 +357,194 @@ impl Interpreter {
             BinaryOperator::Add => Instruction::Add,
             BinaryOperator::Subtract => Instruction::Subtract,
             BinaryOperator::Multiply => Instruction::Multiply,
+            BinaryOperator::Divide if self.options.integer_division => Instruction::FloorDivide,
             BinaryOperator::Divide => Instruction::Divide,
+            BinaryOperator::FloorDiv => Instruction::FloorDivide,
+            BinaryOperator::Modulo => Instruction::Modulo,
+            BinaryOperator::Power => Instruction::PowBuiltin,
+            BinaryOperator::Equal => Instruction::Equal,
+            BinaryOperator::NotEqual => Instruction::NotEqual,
+            BinaryOperator::LessThan => Instruction::LessThan,
+            BinaryOperator::GreaterThan => Instruction::GreaterThan,
+            BinaryOperator::LessEqual => Instruction::LessEqual,
+            BinaryOperator::GreaterEqual => Instruction::GreaterEqual,
+            BinaryOperator::And | BinaryOperator::Or => {
+                panic!("Logical operators are compiled to jumps! ({:?})", op)
+            }
+        }
+    }
+}
+
+// Children are walked with the same indices as ast::child_nodes so paths line up
+impl Visitor for Interpreter {
+    fn visit_number(&mut self, value: f64) {
+        self.emit_constant(value);
+    }
+
+    fn visit_identifier(&mut self, name: &str) {
+        self.emit(Instruction::LoadVariable(name.to_string()));
+    }
+
+    fn visit_unary_op(&mut self, op: &UnaryOperator, operand: &ASTNode) {
+        match (op, operand) {
+            // A negated literal folds straight into the constant
+            (UnaryOperator::Negate, ASTNode::Number(x) | ASTNode::Float(x)) => {
+                self.emit_constant(-x);
+            }
+            (UnaryOperator::Negate, _) => {
+                self.walk_child(0, operand);
+                self.emit(Instruction::Negate);
+            }
+            (UnaryOperator::Not, _) => {
+                self.walk_child(0, operand);
+                self.emit(Instruction::Not);
+            }
+        }
+    }
+
+    fn visit_binary_op(&mut self, left: &ASTNode, op: &BinaryOperator, right: &ASTNode) {
+        if matches!(op, BinaryOperator::And | BinaryOperator::Or) {
+            return self.emit_logical(left, op, right);
+        }
+
+        // Operands are evaluated strictly left to right, so their prints come out in order
+        self.walk_child(0, left);
+        self.walk_child(1, right);
+        let instruction = self.binary_op_to_instruction(op);
+        self.emit(instruction);
+    }
+
+    fn visit_builtin_call(&mut self, name: &str, args: &[ASTNode]) {
+        for (index, arg) in args.iter().enumerate() {
+            self.walk_child(index, arg);
+        }
+
+        let instruction = match name {
+            "sqrt" => Instruction::Sqrt,
+            "abs" => Instruction::Abs,
+            "pow" => Instruction::PowBuiltin,
+            "min" => Instruction::Min,
+            "max" => Instruction::Max,
+            "sin" => Instruction::Sin,
+            "cos" => Instruction::Cos,
+            "exp" => Instruction::Exp,
+            "ln" => Instruction::Ln,
+            "print" => Instruction::Print,
+            other => panic!("Unknown builtin function! ({})", other),
+        };
+        self.emit(instruction);
+    }
+
+    fn visit_assignment(&mut self, variable: &str, value: &ASTNode) {
+        self.walk_child(0, value);
+        // A chained assignment leaves nothing on the stack, so reload what it stored
+        if let ASTNode::Assignment { variable, .. } = value {
+            self.emit(Instruction::LoadVariable(variable.clone()));
+        }
+        self.emit(Instruction::StoreVariable(variable.to_string()));
+    }
+
+    // Laid out like an if/else, so the arm that isn't taken never runs
+    fn visit_ternary(&mut self, condition: &ASTNode, then_value: &ASTNode, else_value: &ASTNode) {
+        self.walk_child(0, condition);
+        let skip_then = self.emit_jump(Instruction::JumpIfFalse(0));
+        self.walk_child(1, then_value);
+        let skip_else = self.emit_jump(Instruction::Jump(0));
+        self.patch_jump(skip_then);
+        self.walk_child(2, else_value);
+        self.patch_jump(skip_else);
+    }
+
+    fn visit_expression_statement(&mut self, expr: &ASTNode) {
+        self.walk_child(0, expr);
+        self.emit(Instruction::Pop);
+    }
+
+    fn visit_if(
+        &mut self,
+        condition: &ASTNode,
+        then_branch: &ASTNode,
+        else_branch: Option<&ASTNode>,
+    ) {
+        self.walk_child(0, condition);
+        let skip_then = self.emit_jump(Instruction::JumpIfFalse(0));
+        self.walk_child(1, then_branch);
+
+        match else_branch {
+            Some(else_branch) => {
+                let skip_else = self.emit_jump(Instruction::Jump(0));
+                self.patch_jump(skip_then);
+                self.walk_child(2, else_branch);
+                self.patch_jump(skip_else);
+            }
+            None => self.patch_jump(skip_then),
+        }
+    }
+
+    fn visit_while(&mut self, condition: &ASTNode, body: &ASTNode) {
+        let loop_start = self.instructions.len();
+        self.walk_child(0, condition);
+        let exit_loop = self.emit_jump(Instruction::JumpIfFalse(0));
+        self.loops.push(LoopContext {
+            start: loop_start,
+            breaks: vec![],
+        });
+        self.walk_child(1, body);
+        self.emit(Instruction::Jump(loop_start));
+        self.patch_jump(exit_loop);
+        if let Some(context) = self.loops.pop() {
+            for jump in context.breaks {
+                self.patch_jump(jump);
+            }
+        }
+    }
+
+    // The analyzer rejects `break` and `continue` outside of a loop
+    fn visit_break(&mut self) {
+        let jump = self.emit_jump(Instruction::Jump(0));
+        self.loops
+            .last_mut()
+            .expect("break outside of a loop")
+            .breaks
+            .push(jump);
+    }
+
+    fn visit_continue(&mut self) {
+        let start = self.loops.last().expect("continue outside of a loop").start;
+        self.emit(Instruction::Jump(start));
+    }
+
+    fn visit_block(&mut self, statements: &[ASTNode]) {
+        for (index, statement) in statements.iter().enumerate() {
+            self.walk_child(index, statement);
+        }
+    }
+
+    // Statements pop their own values, leaving only the result on the stack
+    fn visit_block_expression(&mut self, statements: &[ASTNode], result: &ASTNode) {
+        let mut declared = vec![];
+        for statement in statements {
+            collect_declarations(statement, &mut declared);
+        }
+        self.emit(Instruction::EnterScope(declared));
+        for (index, statement) in statements.iter().enumerate() {
+            self.walk_child(index, statement);
+        }
+        self.walk_child(statements.len(), result);
+        self.emit(Instruction::ExitScope);
+    }
+
+    fn visit_read(&mut self, variable: &str) {
+        self.emit(Instruction::ReadInput(variable.to_string()));
+    }
+
+    fn visit_delete(&mut self, variable: &str) {
+        self.emit(Instruction::DeleteVariable(variable.to_string()));
+    }
+
+    fn visit_program(&mut self, statements: &[ASTNode]) {
+        for (index, statement) in statements.iter().enumerate() {
+            self.walk_child(index, statement);
         }
     }
 }
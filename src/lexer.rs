@@ -1,118 +1,360 @@
+use std::fmt;
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum Token {
     Number(f64),
+    // A number literal written with a decimal point
+    Float(f64),
     Identifier(String),
     Plus,
     Minus,
     Divide,
+    DoubleSlash,
     Multiply,
+    Modulo,
+    Caret,
     Assign,
+    PlusAssign,
+    MinusAssign,
+    MultiplyAssign,
+    DivideAssign,
+    ModuloAssign,
+    PlusPlus,
+    MinusMinus,
+    Bang,
+    And,
+    Or,
+    Equal,
+    NotEqual,
+    Less,
+    Greater,
+    LessEqual,
+    GreaterEqual,
     LParen,
     RParen,
+    LBrace,
+    RBrace,
+    Comma,
+    Colon,
+    Question,
     Semi,
+    If,
+    Else,
+    While,
+    Const,
+    Read,
+    Del,
+    Let,
+    Break,
+    Continue,
     EoF,
 }
 
+// Words that lex as their own token and can't be used as variable names
+pub const KEYWORDS: [(&str, Token); 9] = [
+    ("if", Token::If),
+    ("else", Token::Else),
+    ("while", Token::While),
+    ("const", Token::Const),
+    ("read", Token::Read),
+    ("del", Token::Del),
+    ("let", Token::Let),
+    ("break", Token::Break),
+    ("continue", Token::Continue),
+];
+
+pub fn keyword_token(word: &str) -> Option<Token> {
+    KEYWORDS
+        .iter()
+        .find(|(keyword, _)| *keyword == word)
+        .map(|(_, token)| token.clone())
+}
+
+// The source text of a keyword token, None for any other token
+pub fn keyword_text(token: &Token) -> Option<&'static str> {
+    KEYWORDS
+        .iter()
+        .find(|(_, keyword)| keyword == token)
+        .map(|(text, _)| *text)
+}
+
+// Identifiers start with a Unicode letter or `_`, so `δ` and `área` are names. Digits
+// never start one, that's a number, and only ASCII digits make up numbers. Combining
+// marks aren't letters, so accents must be written precomposed (`á`, not `a` + U+0301),
+// and emoji are rejected as unexpected characters.
+pub fn is_identifier_start(x: char) -> bool {
+    x.is_alphabetic() || x == '_'
+}
+
+// After the first character, any Unicode letter or digit may follow, e.g. `x₁`
+pub fn is_identifier_continue(x: char) -> bool {
+    x.is_alphanumeric() || x == '_'
+}
+
+// 1-based location of a character in the source
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Span {
+    pub line: usize,
+    pub column: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LexError {
+    // A number literal that could not be read, at the index where it starts
+    MalformedNumber { index: usize, reason: String },
+    UnexpectedCharacter { character: char, span: Span },
+}
+
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LexError::MalformedNumber { index, reason } => {
+                write!(f, "Malformed NUMBER at index {}: {}", index, reason)
+            }
+            LexError::UnexpectedCharacter { character, span } => write!(
+                f,
+                "Unexpected character '{}' at line {}, column {}",
+                character, span.line, span.column
+            ),
+        }
+    }
+}
+
+// Runs the lexer to completion, returning every token up to and including EoF
+pub fn tokenize(source: &str) -> Result<Vec<Token>, LexError> {
+    let mut lexer = Lexer::new(source);
+    let mut tokens = vec![lexer.next_token()?];
+    while tokens.last() != Some(&Token::EoF) {
+        tokens.push(lexer.next_token()?);
+    }
+    Ok(tokens)
+}
+
+// Where read_number is within a decimal literal such as `1_000.5e-3`
+#[derive(Clone, Copy, PartialEq)]
+enum NumberState {
+    Integer,
+    Fraction,
+    // Just after the `e`, where a sign may come
+    ExponentStart,
+    // After the exponent's sign, which must be followed by a digit
+    ExponentSign,
+    Exponent,
+}
+
 pub struct Lexer {
     input: Vec<char>,
     position: usize,
     current_char: Option<char>,
+    line: usize,
+    column: usize,
+    token_start: Span,
 }
 
 impl Lexer {
     pub fn new(input: &str) -> Self {
         let chars: Vec<char> = input.chars().collect();
-        let current_char = chars.get(0).copied();
+        let current_char = chars.first().copied();
 
         Lexer {
             input: chars,
             position: 0,
             current_char,
+            line: 1,
+            column: 1,
+            token_start: Span { line: 1, column: 1 },
         }
     }
 
+    // Starts over on new source, keeping the input buffer's allocation
+    pub fn reset(&mut self, input: &str) {
+        self.input.clear();
+        self.input.extend(input.chars());
+        self.position = 0;
+        self.current_char = self.input.first().copied();
+        self.line = 1;
+        self.column = 1;
+        self.token_start = Span { line: 1, column: 1 };
+    }
+
+    // Span of the token most recently returned by next_token
+    pub fn span(&self) -> Span {
+        self.token_start
+    }
+
     fn advance(&mut self) -> bool {
         if self.position >= self.input.len() {
             self.current_char = None;
             return false;
         }
 
+        if self.current_char == Some('\n') {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+
         self.position += 1;
         self.current_char = self.input.get(self.position).copied();
         true
     }
 
+    fn peek(&self) -> Option<char> {
+        self.input.get(self.position + 1).copied()
+    }
+
+    // Skips whitespace and `#` comments, which run to the end of the line
     fn skip_whitespace(&mut self) {
-        while !self.current_char.is_none() && char::is_whitespace(self.current_char.unwrap()) {
-            self.advance();
+        loop {
+            match self.current_char {
+                Some(x) if x.is_whitespace() => {
+                    self.advance();
+                }
+                Some('#') => {
+                    while self.current_char.is_some_and(|x| x != '\n') {
+                        self.advance();
+                    }
+                }
+                _ => break,
+            }
         }
     }
 
-    fn read_number(&mut self) -> f64 {
+    // Reads every number literal: integers (`42`), floats (`4.2`, `.5`, `5.`), scientific
+    // notation (`4.2e-3`) and, through read_radix_number, prefixed ones like `0xff`.
+    // Returns the value and whether it was written as a float (a decimal point or exponent).
+    fn read_number(&mut self) -> Result<(f64, bool), LexError> {
+        if self.current_char == Some('0') && matches!(self.peek(), Some('x' | 'b' | 'o' | 'r')) {
+            return Ok((self.read_radix_number()?, false));
+        }
+
+        let start = self.position;
         let mut num_string = String::new();
-        let mut encountered_decimal = false;
-
-        let result = loop {
-            if char::is_numeric(self.current_char.unwrap_or(' ')) {
-                num_string.push(self.current_char.unwrap());
-            } else if self.current_char.unwrap_or_default() == '.' {
-                if encountered_decimal {
-                    break num_string;
-                }
+        let mut state = NumberState::Integer;
+        let mut mantissa_digits = false;
 
-                encountered_decimal = true;
-                num_string.push('.')
-            } else {
-                break num_string;
-            }
+        while let Some(x) = self.current_char {
+            state = match (state, x) {
+                (NumberState::Integer | NumberState::Fraction, '0'..='9') => {
+                    mantissa_digits = true;
+                    state
+                }
+                (NumberState::Integer, '.') => NumberState::Fraction,
+                (NumberState::Integer | NumberState::Fraction, 'e' | 'E') if mantissa_digits => {
+                    NumberState::ExponentStart
+                }
+                (NumberState::ExponentStart, '+' | '-') => NumberState::ExponentSign,
+                (NumberState::ExponentStart | NumberState::ExponentSign, '0'..='9') => {
+                    NumberState::Exponent
+                }
+                (NumberState::Exponent, '0'..='9') => state,
+                (_, '_') => {
+                    // Digit separators like `1_000` are dropped, but only between two digits
+                    let after_digit = num_string.ends_with(|x: char| x.is_ascii_digit());
+                    if !after_digit || !self.peek().is_some_and(|x| x.is_ascii_digit()) {
+                        return Err(self.malformed_number(start, "'_' must be between two digits"));
+                    }
+                    self.advance();
+                    continue;
+                }
+                _ => break,
+            };
 
+            num_string.push(x);
             self.advance();
-        };
+        }
 
-        if result.len() == 0 {
-            panic!(
-                "Malformed NUMBER, cannot read at index {index} for string \"{string}\"! ({chr})",
-                index = self.position,
-                string = String::from_iter(&self.input),
-                chr = self.current_char.unwrap_or('?')
-            );
+        // `.5` and `5.` are both fine, but a `.` needs a digit on at least one side
+        if !mantissa_digits {
+            return Err(self.malformed_number(start, "expected a digit before or after '.'"));
+        }
+        if matches!(
+            state,
+            NumberState::ExponentStart | NumberState::ExponentSign
+        ) {
+            return Err(self.malformed_number(start, "expected digits after the exponent"));
         }
 
-        result.parse::<f64>().unwrap_or(0.0)
+        match num_string.parse::<f64>() {
+            Ok(value) => Ok((value, state != NumberState::Integer)),
+            Err(_) => Err(self.malformed_number(start, "not a valid number")),
+        }
     }
 
-    fn read_identifier(&mut self) -> String {
-        let mut ident_string = String::new();
-        let mut first_iter = true;
-
-        let result = loop {
-            let valid = match self.current_char {
-                Some(x) if char::is_alphabetic(x) => true,
-                Some(x) if char::is_numeric(x) && !first_iter => true,
-                Some('_') => true,
-                _ => false,
-            };
+    // Reads `0xff` (hex), `0b1010` (binary), `0o17` (octal) or `0r{radix}:{digits}`
+    // for any radix from 2 to 36
+    fn read_radix_number(&mut self) -> Result<f64, LexError> {
+        let start = self.position;
+        self.advance();
 
-            if valid {
-                ident_string.push(self.current_char.unwrap());
-            } else {
-                break ident_string;
+        let radix = match self.current_char {
+            Some(prefix @ ('x' | 'b' | 'o')) => {
+                self.advance();
+                match prefix {
+                    'x' => 16,
+                    'b' => 2,
+                    _ => 8,
+                }
             }
+            _ => {
+                self.advance();
+                let mut radix_string = String::new();
+                while let Some(x) = self.current_char.filter(char::is_ascii_digit) {
+                    radix_string.push(x);
+                    self.advance();
+                }
 
-            first_iter = false;
-            self.advance();
+                if self.current_char != Some(':') {
+                    return Err(self.malformed_number(start, "expected ':' after the radix"));
+                }
+                self.advance();
+
+                match radix_string.parse::<u32>() {
+                    Ok(x) if (2..=36).contains(&x) => x,
+                    _ => return Err(self.malformed_number(start, "radix must be between 2 and 36")),
+                }
+            }
         };
 
-        if result.len() == 0 {
-            panic!(
-                "Malformed IDENTIFIER, cannot read at index {index} for string \"{string}\"! ({chr})",
-                index = self.position,
-                string = String::from_iter(&self.input),
-                chr = self.current_char.unwrap_or('?')
-            );
+        let mut value = 0.0;
+        let mut digit_count = 0;
+        while let Some(x) = self.current_char.filter(|x| x.is_alphanumeric()) {
+            match x.to_digit(radix) {
+                Some(digit) => value = value * radix as f64 + digit as f64,
+                None => {
+                    return Err(self.malformed_number(
+                        start,
+                        &format!("'{}' is not a valid base {} digit", x, radix),
+                    ))
+                }
+            }
+            digit_count += 1;
+            self.advance();
+        }
+
+        if digit_count == 0 {
+            return Err(self.malformed_number(start, "expected digits after the prefix"));
         }
 
-        result
+        Ok(value)
+    }
+
+    fn malformed_number(&self, start: usize, reason: &str) -> LexError {
+        LexError::MalformedNumber {
+            index: start,
+            reason: reason.to_string(),
+        }
+    }
+
+    fn read_identifier(&mut self) -> String {
+        let mut ident_string = String::new();
+        while let Some(x) = self.current_char.filter(|x| is_identifier_continue(*x)) {
+            ident_string.push(x);
+            self.advance();
+        }
+        ident_string
     }
 
     fn match_plain_token(&self, token_char: char) -> Result<Token, &'static str> {
@@ -121,27 +363,84 @@ impl Lexer {
             '-' => Ok(Token::Minus),
             '*' => Ok(Token::Multiply),
             '/' => Ok(Token::Divide),
+            '%' => Ok(Token::Modulo),
+            '^' => Ok(Token::Caret),
+            '!' => Ok(Token::Bang),
             '=' => Ok(Token::Assign),
+            '<' => Ok(Token::Less),
+            '>' => Ok(Token::Greater),
             '(' => Ok(Token::LParen),
             ')' => Ok(Token::RParen),
+            '{' => Ok(Token::LBrace),
+            '}' => Ok(Token::RBrace),
+            ',' => Ok(Token::Comma),
+            ':' => Ok(Token::Colon),
+            '?' => Ok(Token::Question),
             ';' => Ok(Token::Semi),
             _ => Err("unknown token type"),
         }
     }
 
-    pub fn next_token(&mut self) -> Token {
+    // Tokens made of two characters, such as an operator followed by `=`
+    fn match_compound_token(&self, token_char: char) -> Option<Token> {
+        match (token_char, self.peek()?) {
+            ('+', '=') => Some(Token::PlusAssign),
+            ('-', '=') => Some(Token::MinusAssign),
+            ('*', '=') => Some(Token::MultiplyAssign),
+            ('/', '=') => Some(Token::DivideAssign),
+            ('/', '/') => Some(Token::DoubleSlash),
+            ('%', '=') => Some(Token::ModuloAssign),
+            ('+', '+') => Some(Token::PlusPlus),
+            ('-', '-') => Some(Token::MinusMinus),
+            ('&', '&') => Some(Token::And),
+            ('|', '|') => Some(Token::Or),
+            ('=', '=') => Some(Token::Equal),
+            ('!', '=') => Some(Token::NotEqual),
+            ('<', '=') => Some(Token::LessEqual),
+            ('>', '=') => Some(Token::GreaterEqual),
+            _ => None,
+        }
+    }
+
+    pub fn next_token(&mut self) -> Result<Token, LexError> {
         self.skip_whitespace();
+        self.token_start = Span {
+            line: self.line,
+            column: self.column,
+        };
         match self.current_char {
+            Some(x) if self.match_compound_token(x).is_some() => {
+                let token = self.match_compound_token(x).unwrap();
+                self.advance();
+                self.advance();
+                Ok(token)
+            }
             Some(x) if self.match_plain_token(x).is_ok() => {
                 let token = self.match_plain_token(x).unwrap();
                 self.advance();
-                token
+                Ok(token)
             }
-            Some(x) if char::is_numeric(x) || x == '.' => Token::Number(self.read_number()),
-            Some(x) if char::is_alphanumeric(x) || x == '_' => {
-                Token::Identifier(self.read_identifier())
+            Some(x) if x.is_ascii_digit() || x == '.' => match self.read_number()? {
+                (value, true) => Ok(Token::Float(value)),
+                (value, false) => Ok(Token::Number(value)),
+            },
+            Some(x) if is_identifier_start(x) => {
+                let start = self.position;
+                let ident = self.read_identifier();
+                // `_5` is a number with a leading separator rather than a name
+                if ident.starts_with('_')
+                    && ident.contains(|x: char| x.is_ascii_digit())
+                    && ident.chars().all(|x| x == '_' || x.is_ascii_digit())
+                {
+                    return Err(self.malformed_number(start, "'_' must be between two digits"));
+                }
+                Ok(keyword_token(&ident).unwrap_or(Token::Identifier(ident)))
             }
-            None | _ => Token::EoF,
+            Some(character) => Err(LexError::UnexpectedCharacter {
+                character,
+                span: self.token_start,
+            }),
+            None => Ok(Token::EoF),
         }
     }
 }
@@ -1,16 +1,173 @@
 pub mod ast;
+pub mod cache;
 pub mod interpreter;
 pub mod lexer;
+pub mod math;
+pub mod optimizer;
 pub mod parser;
+pub mod prelude;
 pub mod semantic_analyzer;
+pub mod slicing;
 #[cfg(test)]
 mod tests;
 pub mod vm;
 
 // Re-export main types for convenience
 pub use ast::*;
+pub use cache::*;
 pub use interpreter::*;
 pub use lexer::*;
+pub use optimizer::*;
 pub use parser::*;
+pub use prelude::*;
 pub use semantic_analyzer::*;
+pub use slicing::*;
 pub use vm::*;
+
+use std::{collections::HashMap, fmt};
+
+// A failure from any stage of running a program
+#[derive(Debug)]
+pub enum RunError {
+    Parse(ParseError),
+    Semantic(Vec<SemanticError>),
+    Vm(VmError),
+}
+
+impl fmt::Display for RunError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RunError::Parse(err) => write!(f, "Parse error: {}", err),
+            RunError::Semantic(errors) => {
+                let messages: Vec<&str> = errors.iter().map(|x| x.message.as_str()).collect();
+                write!(f, "Semantic error: {}", messages.join("; "))
+            }
+            RunError::Vm(err) => write!(f, "Runtime error: {}", err),
+        }
+    }
+}
+
+impl RunError {
+    // The position of the parse error or first semantic error, if it has one
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            RunError::Parse(err) => err.span(),
+            RunError::Semantic(errors) => errors.first().and_then(|x| x.span),
+            RunError::Vm(_) => None,
+        }
+    }
+}
+
+// Shows the source line a span points into with a caret under its column:
+//
+// 3 | x = y;
+//   |     ^ Variable not in scope: y
+pub fn render_error(source: &str, span: Span, message: &str) -> String {
+    let line = source
+        .lines()
+        .nth(span.line.saturating_sub(1))
+        .unwrap_or("");
+    let gutter = span.line.to_string();
+    format!(
+        "{} | {}\n{} | {}^ {}",
+        gutter,
+        line,
+        " ".repeat(gutter.len()),
+        " ".repeat(span.column.saturating_sub(1)),
+        message
+    )
+}
+
+// Runs every stage on `source` and returns the final variable values
+pub fn run(source: &str) -> Result<HashMap<String, f64>, RunError> {
+    run_and_keep(source).map(|vm| vm.storage().clone())
+}
+
+// Like `run`, but every failure comes back as a message and the storage as
+// `{x: 1, y: 2.5}`, for embedders such as wasm that can't recover from a panic
+pub fn run_checked(source: &str) -> Result<String, String> {
+    run_and_keep(source)
+        .map(|vm| vm.format_storage())
+        .map_err(|err| err.to_string())
+}
+
+// Like `run`, but a final expression statement is kept as the program's
+// result, so `x = 2; x + 3` gives `{x: 2}` and `Some(5.0)`
+pub fn run_repl(source: &str) -> Result<(HashMap<String, f64>, Option<f64>), RunError> {
+    let mut program = parse(source)?;
+    let has_result = keep_last_expression(&mut program);
+
+    let program = compile_program(&program)?;
+    let mut vm = VM::new(
+        program.instructions,
+        program.constants,
+        program.symbol_table,
+    );
+    vm.execute().map_err(RunError::Vm)?;
+
+    let result = if has_result { vm.result() } else { None };
+    Ok((vm.storage().clone(), result))
+}
+
+// Unwraps a trailing expression statement so its value is left on the stack
+// instead of popped, returning whether there was one
+pub fn keep_last_expression(program: &mut ASTNode) -> bool {
+    let ASTNode::Program(statements) = program else {
+        return false;
+    };
+    match statements.pop() {
+        Some(ASTNode::ExpressionStatement(expr)) => {
+            statements.push(*expr);
+            true
+        }
+        Some(statement) => {
+            statements.push(statement);
+            false
+        }
+        None => false,
+    }
+}
+
+// Runs a program to completion and hands back the VM, so its storage and
+// symbol table can be carried into later fragments
+pub fn run_and_keep(source: &str) -> Result<VM, RunError> {
+    let program = compile(source)?;
+    let mut vm = VM::new(
+        program.instructions,
+        program.constants,
+        program.symbol_table,
+    );
+    vm.execute().map_err(RunError::Vm)?;
+
+    Ok(vm)
+}
+
+// Everything the VM needs to run a program
+#[derive(Clone)]
+pub struct CompiledProgram {
+    pub instructions: Vec<Instruction>,
+    pub constants: Vec<f64>,
+    pub symbol_table: SymbolTable,
+}
+
+// Parses, analyzes and generates code for `source`, the stages shared by every run
+pub fn compile(source: &str) -> Result<CompiledProgram, RunError> {
+    compile_program(&parse(source)?)
+}
+
+fn parse(source: &str) -> Result<ASTNode, RunError> {
+    let mut parser = Parser::new(Lexer::new(source));
+    parser.parse_program().map_err(RunError::Parse)
+}
+
+fn compile_program(program: &ASTNode) -> Result<CompiledProgram, RunError> {
+    let mut analyzer = SemanticAnalyzer::new();
+    analyzer.analyze(program).map_err(RunError::Semantic)?;
+
+    let (instructions, constants) = Interpreter::new().generate_instructions(program);
+    Ok(CompiledProgram {
+        instructions,
+        constants,
+        symbol_table: analyzer.symbol_table,
+    })
+}
@@ -1,22 +1,145 @@
-use micro_lang::{Interpreter, Lexer, Parser, SemanticAnalyzer, VM};
+use std::{
+    collections::HashMap,
+    env, fs,
+    io::{self, Write},
+    process,
+};
+
+use micro_lang::{
+    keep_last_expression, FormatOptions, Interpreter, Lexer, Parser, SemanticAnalyzer, VM,
+};
+
+const USAGE: &str = "Usage: micro_lang [run <file> | dump <file>]";
 
 fn main() {
-    let input = r#"
-    x = (10 + 5 * 2) / 4;
-    y = x + 10;
-    z = y - y / 5;
-    "#;
-    let lexer = Lexer::new(input);
-    let mut parser = Parser::new(lexer);
+    let args: Vec<String> = env::args().collect();
+
+    let command = match args.get(1).map(String::as_str) {
+        None => return repl(),
+        Some(command @ ("run" | "dump")) => command,
+        Some(other) => exit_with_message(&format!("Unknown command \"{}\"\n{}", other, USAGE)),
+    };
+
+    let input = match args.get(2) {
+        Some(path) => read_source(path),
+        None => exit_with_message(USAGE),
+    };
+
+    match command {
+        "dump" => dump(&input),
+        _ => run(&input),
+    }
+}
+
+fn read_source(path: &str) -> String {
+    match fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(err) => exit_with_message(&format!("Could not read \"{}\": {}", path, err)),
+    }
+}
+
+fn exit_with_message(message: &str) -> ! {
+    eprintln!("{}", message);
+    process::exit(1)
+}
+
+fn run(input: &str) {
+    match micro_lang::run_and_keep(input) {
+        Ok(vm) => println!("{}", vm.format_storage()),
+        Err(err) => match err.span() {
+            Some(span) => {
+                exit_with_message(&micro_lang::render_error(input, span, &err.to_string()))
+            }
+            None => exit_with_message(&err.to_string()),
+        },
+    }
+}
+
+// Prints the generated instructions with their indices, so jump targets can be followed
+fn dump(input: &str) {
+    let mut parser = Parser::new(Lexer::new(input));
+    let program = match parser.parse_program() {
+        Ok(program) => program,
+        Err(err) => exit_with_message(&format!("Parse error: {}", err)),
+    };
+
+    let (instructions, constants) = Interpreter::new().generate_instructions(&program);
+    for (index, constant) in constants.iter().enumerate() {
+        println!("{:>4}  = {}", format!("#{}", index), constant);
+    }
+    for (index, instruction) in instructions.iter().enumerate() {
+        println!("{:>4}  {}", index, instruction);
+    }
+}
+
+// Reads statements line by line, keeping variables alive between lines
+fn repl() {
     let mut analyzer = SemanticAnalyzer::new();
+    let mut storage: HashMap<String, f64> = HashMap::new();
 
-    let program = &parser.parse_program().ok().unwrap();
-    _ = analyzer.analyze(program);
+    print_prompt();
+    for line in io::stdin().lines() {
+        let Ok(line) = line else {
+            break;
+        };
 
-    let mut interpreter = Interpreter::new();
-    let instructions = interpreter.generate_instructions(program);
+        if !line.trim().is_empty() {
+            storage = eval_line(&line, &mut analyzer, storage);
+        }
+        print_prompt();
+    }
 
-    let mut vm = VM::new(instructions, analyzer.symbol_table);
+    // Leave the terminal on a fresh line after Ctrl-D
+    println!();
+}
+
+fn eval_line(
+    line: &str,
+    analyzer: &mut SemanticAnalyzer,
+    storage: HashMap<String, f64>,
+) -> HashMap<String, f64> {
+    let mut parser = Parser::new(Lexer::new(line));
+    let mut program = match parser.parse_program() {
+        Ok(program) => program,
+        Err(err) => {
+            println!("Parse error: {}", err);
+            return storage;
+        }
+    };
+
+    let has_result = keep_last_expression(&mut program);
+
+    // A line that fails, whether in analysis or at runtime, must not leave its
+    // declarations behind
+    let checkpoint = analyzer.symbol_table.snapshot();
+    if let Err(errors) = analyzer.analyze(&program) {
+        analyzer.symbol_table.restore(checkpoint);
+        analyzer.clear_errors();
+        for error in errors {
+            println!("Semantic error: {}", error.message);
+        }
+        return storage;
+    }
+
+    let (instructions, constants) = Interpreter::new().generate_instructions(&program);
+    let mut vm = VM::new(instructions, constants, analyzer.symbol_table.clone());
+    match vm.execute_with_inputs(storage.clone()) {
+        Ok(storage) => {
+            match vm.result().filter(|_| has_result) {
+                Some(value) => println!("{}", FormatOptions::default().format(value)),
+                None => println!("{}", vm.format_storage()),
+            }
+            storage
+        }
+        Err(err) => {
+            analyzer.symbol_table.restore(checkpoint);
+            println!("Runtime error: {}", err);
+            storage
+        }
+    }
+}
 
-    println!("{:?}", vm.execute());
+fn print_prompt() {
+    print!(">> ");
+    _ = io::stdout().flush();
 }
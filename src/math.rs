@@ -0,0 +1,251 @@
+// Transcendental functions for the VM. In Deterministic mode these only use
+// basic IEEE 754 operations (+, -, *, /), which are correctly rounded on every
+// platform, so results are bit-identical regardless of the system libm.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MathMode {
+    Native,
+    Deterministic,
+}
+
+pub fn sin(mode: MathMode, x: f64) -> f64 {
+    match mode {
+        MathMode::Native => x.sin(),
+        MathMode::Deterministic => soft::sin(x),
+    }
+}
+
+pub fn cos(mode: MathMode, x: f64) -> f64 {
+    match mode {
+        MathMode::Native => x.cos(),
+        MathMode::Deterministic => soft::cos(x),
+    }
+}
+
+pub fn exp(mode: MathMode, x: f64) -> f64 {
+    match mode {
+        MathMode::Native => x.exp(),
+        MathMode::Deterministic => soft::exp(x),
+    }
+}
+
+pub fn ln(mode: MathMode, x: f64) -> f64 {
+    match mode {
+        MathMode::Native => x.ln(),
+        MathMode::Deterministic => soft::ln(x),
+    }
+}
+
+pub fn pow(mode: MathMode, base: f64, exponent: f64) -> f64 {
+    match mode {
+        MathMode::Native => base.powf(exponent),
+        MathMode::Deterministic => soft::pow(base, exponent),
+    }
+}
+
+mod soft {
+    // pi/2 and ln(2) split into a high part with trailing zero bits and a low
+    // correction, so `k * HI` is exact during range reduction
+    const FRAC_PI_2_HI: f64 = 1.570_796_326_734_125_6;
+    const FRAC_PI_2_LO: f64 = 6.077_100_506_506_192e-11;
+    const LN_2_HI: f64 = 6.931_471_803_691_238e-1;
+    const LN_2_LO: f64 = 1.908_214_929_270_587_7e-10;
+
+    // Reduces x to r in [-pi/4, pi/4] and the quadrant x fell in
+    fn reduce_quadrant(x: f64) -> (f64, i64) {
+        let k = (x / std::f64::consts::FRAC_PI_2).round();
+        let r = (x - k * FRAC_PI_2_HI) - k * FRAC_PI_2_LO;
+        (r, (k as i64).rem_euclid(4))
+    }
+
+    // Taylor series of sin around 0, accurate for |r| <= pi/4
+    fn sin_series(r: f64) -> f64 {
+        let r2 = r * r;
+        let mut term = r;
+        let mut sum = r;
+        for n in 1..10 {
+            term = -term * r2 / ((2 * n) as f64 * (2 * n + 1) as f64);
+            sum += term;
+        }
+        sum
+    }
+
+    // Taylor series of cos around 0, accurate for |r| <= pi/4
+    fn cos_series(r: f64) -> f64 {
+        let r2 = r * r;
+        let mut term = 1.0;
+        let mut sum = 1.0;
+        for n in 1..10 {
+            term = -term * r2 / ((2 * n - 1) as f64 * (2 * n) as f64);
+            sum += term;
+        }
+        sum
+    }
+
+    pub fn sin(x: f64) -> f64 {
+        if !x.is_finite() {
+            return f64::NAN;
+        }
+
+        let (r, quadrant) = reduce_quadrant(x);
+        match quadrant {
+            0 => sin_series(r),
+            1 => cos_series(r),
+            2 => -sin_series(r),
+            _ => -cos_series(r),
+        }
+    }
+
+    pub fn cos(x: f64) -> f64 {
+        if !x.is_finite() {
+            return f64::NAN;
+        }
+
+        let (r, quadrant) = reduce_quadrant(x);
+        match quadrant {
+            0 => cos_series(r),
+            1 => -sin_series(r),
+            2 => -cos_series(r),
+            _ => sin_series(r),
+        }
+    }
+
+    // Multiplies by 2^k one step at a time so no intermediate overflows early
+    fn scale_by_power_of_two(mut value: f64, k: i64) -> f64 {
+        let step = if k > 0 { 2.0 } else { 0.5 };
+        for _ in 0..k.unsigned_abs() {
+            value *= step;
+            if value == 0.0 || value.is_infinite() {
+                break;
+            }
+        }
+        value
+    }
+
+    pub fn exp(x: f64) -> f64 {
+        if x.is_nan() {
+            return f64::NAN;
+        }
+        if x > 709.8 {
+            return f64::INFINITY;
+        }
+        if x < -745.2 {
+            return 0.0;
+        }
+
+        // e^x = 2^k * e^r with |r| <= ln(2)/2
+        let k = (x / std::f64::consts::LN_2).round();
+        let r = (x - k * LN_2_HI) - k * LN_2_LO;
+
+        let mut term = 1.0;
+        let mut sum = 1.0;
+        for n in 1..20 {
+            term = term * r / n as f64;
+            sum += term;
+        }
+
+        scale_by_power_of_two(sum, k as i64)
+    }
+
+    pub fn ln(x: f64) -> f64 {
+        if x.is_nan() || x < 0.0 {
+            return f64::NAN;
+        }
+        if x == 0.0 {
+            return f64::NEG_INFINITY;
+        }
+        if x.is_infinite() {
+            return f64::INFINITY;
+        }
+
+        // x = m * 2^k with m in [sqrt(1/2), sqrt(2))
+        let mut m = x;
+        let mut k = 0;
+        while m >= std::f64::consts::SQRT_2 {
+            m *= 0.5;
+            k += 1;
+        }
+        while m < std::f64::consts::FRAC_1_SQRT_2 {
+            m *= 2.0;
+            k -= 1;
+        }
+
+        // ln(m) = 2 * atanh(s) = 2 * (s + s^3/3 + s^5/5 + ...)
+        let s = (m - 1.0) / (m + 1.0);
+        let s2 = s * s;
+        let mut power = s;
+        let mut sum = s;
+        for n in 1..15 {
+            power *= s2;
+            sum += power / (2 * n + 1) as f64;
+        }
+
+        let k = k as f64;
+        (k * LN_2_HI + 2.0 * sum) + k * LN_2_LO
+    }
+
+    pub fn pow(base: f64, exponent: f64) -> f64 {
+        if exponent == 0.0 {
+            return 1.0;
+        }
+        if base.is_nan() || exponent.is_nan() {
+            return f64::NAN;
+        }
+
+        // Integer exponents use exact repeated squaring
+        if exponent.fract() == 0.0 && exponent.abs() <= 1024.0 {
+            let mut result = 1.0;
+            let mut square = base;
+            let mut n = exponent.abs() as u64;
+            while n > 0 {
+                if n & 1 == 1 {
+                    result *= square;
+                }
+                square *= square;
+                n >>= 1;
+            }
+            return if exponent < 0.0 { 1.0 / result } else { result };
+        }
+
+        if base < 0.0 {
+            return f64::NAN;
+        }
+        if base == 0.0 {
+            return if exponent > 0.0 { 0.0 } else { f64::INFINITY };
+        }
+
+        exp(exponent * ln(base))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deterministic_sin() {
+        let value = sin(MathMode::Deterministic, 1.0);
+
+        assert_eq!(value.to_bits(), 0.8414709848078965_f64.to_bits());
+        assert!((value - 1.0_f64.sin()).abs() < 1e-15);
+    }
+
+    #[test]
+    fn test_deterministic_functions_match_native() {
+        let inputs = [-7.5, -1.0, -0.25, 0.0, 0.5, 1.0, 2.0, 10.0, 100.0];
+        for x in inputs {
+            let native = cos(MathMode::Native, x);
+            assert!((cos(MathMode::Deterministic, x) - native).abs() < 1e-12);
+
+            let native = exp(MathMode::Native, x);
+            assert!((exp(MathMode::Deterministic, x) - native).abs() <= native * 1e-12);
+        }
+
+        for x in [0.001, 0.5, 1.0, 2.0, 1e10] {
+            assert!((ln(MathMode::Deterministic, x) - ln(MathMode::Native, x)).abs() < 1e-12);
+        }
+
+        assert_eq!(pow(MathMode::Deterministic, 2.0, 10.0), 1024.0);
+        assert_eq!(pow(MathMode::Deterministic, 2.0, -1.0), 0.5);
+        assert!((pow(MathMode::Deterministic, 9.0, 0.5) - 3.0).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,212 @@
+use crate::ast::{boxed_node, child_nodes, evaluate_constant, ASTNode, BinaryOperator};
+use crate::interpreter::Instruction;
+use crate::slicing::{usage_of, VariableUsage};
+use std::collections::HashSet;
+
+// Rewrites the tree without changing what the program computes
+pub fn optimize(node: &ASTNode) -> ASTNode {
+    match node {
+        ASTNode::Program(statements) => {
+            ASTNode::Program(eliminate_dead_assignments(optimize_statements(statements)))
+        }
+        ASTNode::Block(statements) => ASTNode::Block(optimize_statements(statements)),
+        ASTNode::BlockExpression { statements, result } => ASTNode::BlockExpression {
+            statements: optimize_statements(statements),
+            result: boxed_node(optimize(result)),
+        },
+        ASTNode::If {
+            condition,
+            then_branch,
+            else_branch,
+        } => ASTNode::If {
+            condition: boxed_node(optimize(condition)),
+            then_branch: boxed_node(optimize(then_branch)),
+            else_branch: else_branch.as_deref().map(|x| boxed_node(optimize(x))),
+        },
+        ASTNode::While { condition, body } => ASTNode::While {
+            condition: boxed_node(optimize(condition)),
+            body: boxed_node(optimize(body)),
+        },
+        ASTNode::Assignment {
+            variable,
+            declared_type,
+            constant,
+            declaration,
+            value,
+            span,
+        } => ASTNode::Assignment {
+            variable: variable.clone(),
+            declared_type: declared_type.clone(),
+            constant: *constant,
+            declaration: *declaration,
+            value: boxed_node(optimize(value)),
+            span: *span,
+        },
+        ASTNode::Ternary {
+            condition,
+            then_value,
+            else_value,
+        } => ASTNode::Ternary {
+            condition: boxed_node(optimize(condition)),
+            then_value: boxed_node(optimize(then_value)),
+            else_value: boxed_node(optimize(else_value)),
+        },
+        ASTNode::ExpressionStatement(expr) => {
+            ASTNode::ExpressionStatement(boxed_node(optimize(expr)))
+        }
+        ASTNode::UnaryOp { op, operand } => ASTNode::UnaryOp {
+            op: op.clone(),
+            operand: boxed_node(optimize(operand)),
+        },
+        ASTNode::BuiltinCall { name, args } => ASTNode::BuiltinCall {
+            name: name.clone(),
+            args: args.iter().map(optimize).collect(),
+        },
+        ASTNode::BinaryOp { left, op, right } => {
+            simplify_identity(optimize(left), op, optimize(right))
+        }
+        other => other.clone(),
+    }
+}
+
+// Applies `x * 1 = x`, `x + 0 = x`, `x * 0 = 0`, `x - 0 = x` and `x / 1 = x`.
+// `x * 0` keeps `x` when it prints, since dropping it would lose the output.
+fn simplify_identity(left: ASTNode, op: &BinaryOperator, right: ASTNode) -> ASTNode {
+    let (left_value, right_value) = (evaluate_constant(&left), evaluate_constant(&right));
+    match (op, left_value, right_value) {
+        (BinaryOperator::Multiply, _, Some(1.0)) => left,
+        (BinaryOperator::Multiply, Some(1.0), _) => right,
+        (BinaryOperator::Multiply, _, Some(0.0)) if !has_side_effects(&left) => right,
+        (BinaryOperator::Multiply, Some(0.0), _) if !has_side_effects(&right) => left,
+        (BinaryOperator::Add, _, Some(0.0)) => left,
+        (BinaryOperator::Add, Some(0.0), _) => right,
+        (BinaryOperator::Subtract, _, Some(0.0)) => left,
+        (BinaryOperator::Divide, _, Some(1.0)) => left,
+        _ => ASTNode::BinaryOp {
+            left: boxed_node(left),
+            op: op.clone(),
+            right: boxed_node(right),
+        },
+    }
+}
+
+// Whether evaluating `node` can do more than produce a value, e.g. print or
+// assign inside a block expression, so it can't be dropped
+fn has_side_effects(node: &ASTNode) -> bool {
+    matches!(
+        node,
+        ASTNode::Assignment { .. }
+            | ASTNode::Read(_)
+            | ASTNode::Delete(_)
+            | ASTNode::BuiltinCall { .. }
+    ) || child_nodes(node).into_iter().any(has_side_effects)
+}
+
+fn optimize_statements(statements: &[ASTNode]) -> Vec<ASTNode> {
+    statements.iter().filter_map(eliminate_dead_code).collect()
+}
+
+// Drops `x = ...;` when a later top-level statement overwrites `x` before anything
+// reads it. Only plain assignments and reads count as overwriting, since a write
+// inside an if or while might not happen and a declaration can't follow a write.
+fn eliminate_dead_assignments(statements: Vec<ASTNode>) -> Vec<ASTNode> {
+    let usages: Vec<VariableUsage> = statements.iter().map(usage_of).collect();
+    let dead: Vec<bool> = (0..statements.len())
+        .map(|i| is_dead_assignment(&statements, &usages, i))
+        .collect();
+
+    statements
+        .into_iter()
+        .zip(dead)
+        .filter_map(|(statement, dead)| (!dead).then_some(statement))
+        .collect()
+}
+
+fn is_dead_assignment(statements: &[ASTNode], usages: &[VariableUsage], index: usize) -> bool {
+    // Typed, const and let assignments declare the variable, so they have to stay
+    let ASTNode::Assignment {
+        variable,
+        declared_type: None,
+        constant: false,
+        declaration: false,
+        value,
+        ..
+    } = &statements[index]
+    else {
+        return false;
+    };
+
+    // Chained assignments and block expressions can write other variables too,
+    // and a value with side effects has to run even if the variable is never read
+    if usages[index].writes.len() != 1 || has_side_effects(value) {
+        return false;
+    }
+
+    for (statement, usage) in statements.iter().zip(usages).skip(index + 1) {
+        if usage.reads.contains(variable) {
+            return false;
+        }
+        let overwrites = matches!(
+            statement,
+            ASTNode::Assignment {
+                declared_type: None,
+                constant: false,
+                declaration: false,
+                ..
+            } | ASTNode::Read(_)
+        );
+        if overwrites && usage.writes.contains(variable) {
+            return true;
+        }
+    }
+
+    false
+}
+
+// Returns None if the statement can never run. Conditions are simplified first,
+// so `if (x * 0)` is just as dead as `if (0)`.
+fn eliminate_dead_code(statement: &ASTNode) -> Option<ASTNode> {
+    match statement {
+        ASTNode::If {
+            condition,
+            then_branch,
+            else_branch,
+        } => match evaluate_constant(&optimize(condition)) {
+            Some(x) if x != 0.0 => Some(optimize(then_branch)),
+            Some(_) => else_branch.as_deref().map(optimize),
+            None => Some(optimize(statement)),
+        },
+        ASTNode::While { condition, .. }
+            if evaluate_constant(&optimize(condition)) == Some(0.0) =>
+        {
+            None
+        }
+        _ => Some(optimize(statement)),
+    }
+}
+
+// Peephole pass over generated code. `STORE v; LOAD v` becomes `DUP; STORE v`, which
+// keeps the value on the stack instead of reading it back. The length never changes,
+// so jump targets stay valid, but a LOAD that is itself a jump target is left alone.
+pub fn optimize_instructions(mut instructions: Vec<Instruction>) -> Vec<Instruction> {
+    let targets: HashSet<usize> = instructions
+        .iter()
+        .filter_map(Instruction::target)
+        .collect();
+
+    for i in 1..instructions.len() {
+        let reloads_store = match (&instructions[i - 1], &instructions[i]) {
+            (Instruction::StoreVariable(stored), Instruction::LoadVariable(loaded)) => {
+                stored == loaded
+            }
+            _ => false,
+        };
+
+        if reloads_store && !targets.contains(&i) {
+            instructions.swap(i - 1, i);
+            instructions[i - 1] = Instruction::Dup;
+        }
+    }
+
+    instructions
+}
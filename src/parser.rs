@@ -1,97 +1,414 @@
-use crate::ast::{boxed_node, token_to_binary_op, ASTNode};
-use crate::lexer::{Lexer, Token};
+use std::{fmt, mem};
+
+use crate::ast::{
+    boxed_node, child_nodes, token_to_binary_op, ASTNode, BinaryOperator, UnaryOperator,
+};
+use crate::lexer::{keyword_text, LexError, Lexer, Span, Token};
+use crate::prelude::builtin_arity;
+use crate::semantic_analyzer::Type;
+
+// Parentheses, negations and blocks nested deeper than this are rejected
+// rather than risking a stack overflow
+pub const DEFAULT_MAX_NESTING_DEPTH: usize = 256;
+
+// Operator chains like `1 + 1 + ...` are built in a loop rather than by recursion,
+// but every later stage recurses over the tree, so its depth is capped too
+pub const MAX_EXPRESSION_DEPTH: usize = 1024;
+
+// (left, right) binding powers of the infix operators, loosest first. A right power
+// above the left one makes an operator left-associative, below makes it right-associative.
+fn infix_binding_power(token: &Token) -> Option<(u8, u8)> {
+    match token {
+        Token::Or => Some((1, 2)),
+        Token::And => Some((3, 4)),
+        Token::Equal
+        | Token::NotEqual
+        | Token::Less
+        | Token::Greater
+        | Token::LessEqual
+        | Token::GreaterEqual => Some(COMPARISON_BINDING_POWER),
+        Token::Plus | Token::Minus => Some((7, 8)),
+        Token::Multiply | Token::Divide | Token::DoubleSlash | Token::Modulo => Some((9, 10)),
+        Token::Caret => Some((13, 12)),
+        _ => None,
+    }
+}
+
+const COMPARISON_BINDING_POWER: (u8, u8) = (5, 6);
+
+// For `-` and `!`, between `*` and `^`, so `-a * b` is `(-a) * b` but `-2 ^ 2` is `-(2 ^ 2)`
+const PREFIX_BINDING_POWER: u8 = 11;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseError {
+    UnexpectedToken {
+        expected: String,
+        found: Token,
+    },
+    UnexpectedEof,
+    InvalidOperator(Token),
+    // A '(' with no matching ')', at the position of the '('
+    UnclosedParen(Span),
+    // A '{' still open at the end of input, at the position of the '{'
+    UnclosedBrace(Span),
+    // A ')' with no matching '('
+    UnexpectedRParen(Span),
+    // `()` where an expression was expected, at the position of the '('
+    EmptyParens(Span),
+    Lex(LexError),
+    // Nesting went past the parser's limit, which is carried here
+    NestingTooDeep(usize),
+    // An expression tree deeper than MAX_EXPRESSION_DEPTH, e.g. a very long operator chain
+    ExpressionTooDeep(usize),
+    // A keyword such as `if` written where a variable name was expected
+    ReservedKeyword {
+        keyword: String,
+        span: Span,
+    },
+    // `a < b < c`, at the position of the second operator
+    ChainedComparison {
+        first: BinaryOperator,
+        second: BinaryOperator,
+        span: Span,
+    },
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::UnexpectedToken { expected, found } => write!(
+                f,
+                "Token ({:?}) did not match expected ({})",
+                found, expected
+            ),
+            ParseError::UnexpectedEof => write!(f, "Unexpected end of input"),
+            ParseError::InvalidOperator(token) => {
+                write!(f, "Token {:?} was not a binary operator", token)
+            }
+            ParseError::UnclosedParen(span) => write!(
+                f,
+                "Unmatched '(' opened at line {}, column {}",
+                span.line, span.column
+            ),
+            ParseError::UnclosedBrace(span) => write!(
+                f,
+                "Unmatched '{{' opened at line {}, column {}, the input ended before its '}}'",
+                span.line, span.column
+            ),
+            ParseError::UnexpectedRParen(span) => write!(
+                f,
+                "Unmatched ')' at line {}, column {}",
+                span.line, span.column
+            ),
+            ParseError::EmptyParens(span) => write!(
+                f,
+                "Parentheses at line {}, column {} contain no expression",
+                span.line, span.column
+            ),
+            ParseError::Lex(err) => write!(f, "{}", err),
+            ParseError::NestingTooDeep(limit) => {
+                write!(f, "Input is nested more than {} levels deep", limit)
+            }
+            ParseError::ExpressionTooDeep(limit) => {
+                write!(f, "Expression is more than {} operators deep", limit)
+            }
+            ParseError::ReservedKeyword { keyword, span } => write!(
+                f,
+                "'{}' is a reserved keyword and cannot be used as a name (line {}, column {})",
+                keyword, span.line, span.column
+            ),
+            ParseError::ChainedComparison {
+                first,
+                second,
+                span,
+            } => write!(
+                f,
+                "Comparisons can't be chained (line {}, column {}), write `a {} b && b {} c` instead of `a {} b {} c`",
+                span.line, span.column, first, second, first, second
+            ),
+        }
+    }
+}
+
+impl ParseError {
+    // Where in the source the error points, for errors that know it
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            ParseError::UnclosedParen(span)
+            | ParseError::UnclosedBrace(span)
+            | ParseError::UnexpectedRParen(span)
+            | ParseError::EmptyParens(span)
+            | ParseError::ReservedKeyword { span, .. }
+            | ParseError::ChainedComparison { span, .. }
+            | ParseError::Lex(LexError::UnexpectedCharacter { span, .. }) => Some(*span),
+            _ => None,
+        }
+    }
+}
+
+// Levels from `node` down to its deepest leaf, counting itself
+fn tree_height(node: &ASTNode) -> usize {
+    1 + child_nodes(node)
+        .into_iter()
+        .map(tree_height)
+        .max()
+        .unwrap_or(0)
+}
+
+// Where the parser's tokens come from
+enum TokenSource {
+    Lexer(Lexer),
+    // Tokens given up front, which have no source text to take positions from
+    Tokens { tokens: Vec<Token>, position: usize },
+}
+
+impl TokenSource {
+    fn next_token(&mut self) -> Result<Token, LexError> {
+        match self {
+            TokenSource::Lexer(lexer) => lexer.next_token(),
+            TokenSource::Tokens { tokens, position } => {
+                let token = tokens.get(*position).cloned().unwrap_or(Token::EoF);
+                *position += 1;
+                Ok(token)
+            }
+        }
+    }
+
+    // For given tokens, the column is the token's index, counting from 1
+    fn span(&self) -> Span {
+        match self {
+            TokenSource::Lexer(lexer) => lexer.span(),
+            TokenSource::Tokens { position, .. } => Span {
+                line: 1,
+                column: *position,
+            },
+        }
+    }
+}
 
 pub struct Parser {
-    lexer: Lexer,
+    tokens: TokenSource,
     current_token: Token,
+    current_span: Span,
+    // One token of lookahead, to tell assignments from expression statements
+    next_token: Result<Token, LexError>,
+    next_span: Span,
+    // A failure to lex the first token, reported by the first parse call
+    initial_error: Option<LexError>,
+    depth: usize,
+    max_depth: usize,
 }
 
 impl Parser {
-    pub fn new(mut lexer: Lexer) -> Self {
-        let current_token = lexer.next_token();
+    pub fn new(lexer: Lexer) -> Self {
+        Self::from_source(TokenSource::Lexer(lexer))
+    }
+
+    // Parses a token stream built without the lexer, e.g. by a tool or a test.
+    // It doesn't need to end with EoF, running out of tokens implies one.
+    pub fn from_tokens(tokens: Vec<Token>) -> Self {
+        Self::from_source(TokenSource::Tokens {
+            tokens,
+            position: 0,
+        })
+    }
+
+    fn from_source(mut tokens: TokenSource) -> Self {
+        let (current_token, initial_error) = match tokens.next_token() {
+            Ok(token) => (token, None),
+            Err(err) => (Token::EoF, Some(err)),
+        };
+        let current_span = tokens.span();
+        let next_token = tokens.next_token();
+        let next_span = tokens.span();
         Parser {
-            lexer,
+            tokens,
             current_token,
+            current_span,
+            next_token,
+            next_span,
+            initial_error,
+            depth: 0,
+            max_depth: DEFAULT_MAX_NESTING_DEPTH,
         }
     }
 
-    fn advance(&mut self) {
-        self.current_token = self.lexer.next_token();
+    pub fn set_max_depth(&mut self, depth: usize) {
+        self.max_depth = depth;
     }
 
-    fn expect_number_token(&mut self) -> Result<Token, String> {
+    // Runs `parse` one nesting level deeper, failing once the limit is reached
+    fn nested<T>(
+        &mut self,
+        parse: impl FnOnce(&mut Self) -> Result<T, ParseError>,
+    ) -> Result<T, ParseError> {
+        if self.depth >= self.max_depth {
+            return Err(ParseError::NestingTooDeep(self.max_depth));
+        }
+
+        self.depth += 1;
+        let result = parse(self);
+        self.depth -= 1;
+        result
+    }
+
+    // Lexing stops at the first error, which is reported once the parser reaches it
+    fn advance(&mut self) -> Result<(), ParseError> {
+        let token = mem::replace(&mut self.next_token, Ok(Token::EoF));
+        self.current_token = token.map_err(ParseError::Lex)?;
+        self.current_span = self.next_span;
+        self.next_token = self.tokens.next_token();
+        self.next_span = self.tokens.span();
+        Ok(())
+    }
+
+    fn check_initial_error(&mut self) -> Result<(), ParseError> {
+        match self.initial_error.take() {
+            Some(err) => Err(ParseError::Lex(err)),
+            None => Ok(()),
+        }
+    }
+
+    // Error for the current token not being what the grammar expected
+    fn unexpected(&self, expected: impl Into<String>) -> ParseError {
+        match self.current_token {
+            Token::EoF => ParseError::UnexpectedEof,
+            Token::RParen => ParseError::UnexpectedRParen(self.current_span),
+            _ => ParseError::UnexpectedToken {
+                expected: expected.into(),
+                found: self.current_token.clone(),
+            },
+        }
+    }
+
+    // Like `unexpected`, for where a name was expected, so a keyword written
+    // there is reported as one
+    fn unexpected_name(&self, expected: &str) -> ParseError {
+        match keyword_text(&self.current_token) {
+            Some(keyword) => ParseError::ReservedKeyword {
+                keyword: keyword.to_string(),
+                span: self.current_span,
+            },
+            None => self.unexpected(expected),
+        }
+    }
+
+    fn expect_number_token(&mut self) -> Result<Token, ParseError> {
         match self.current_token.clone() {
-            Token::Number(x) => {
-                self.advance();
-                Ok(Token::Number(x))
+            token @ (Token::Number(_) | Token::Float(_)) => {
+                self.advance()?;
+                Ok(token)
             }
-            other => Err(format!(
-                "Token ({:?}) was expected to be a Number! (was {:?})",
-                self.current_token, other
-            )),
+            _ => Err(self.unexpected("Number")),
         }
     }
 
-    fn expect_identifier_token(&mut self) -> Result<Token, String> {
+    fn expect_identifier_token(&mut self) -> Result<Token, ParseError> {
         match self.current_token.clone() {
             Token::Identifier(x) => {
-                self.advance();
+                self.advance()?;
                 Ok(Token::Identifier(x))
             }
-            other => Err(format!(
-                "Token ({:?}) was expected to be an Identifier! (was {:?})",
-                self.current_token, other
-            )),
+            _ => Err(self.unexpected_name("Identifier")),
         }
     }
 
-    fn expect_identifier_or_number_token(&mut self) -> Result<Token, String> {
+    fn expect_identifier_or_number_token(&mut self) -> Result<Token, ParseError> {
         match self.current_token.clone() {
             Token::Identifier(_) => self.expect_identifier_token(),
-            Token::Number(_) => self.expect_number_token(),
-            other => Err(format!(
-                "Token ({:?}) was neither Number nor Identifier! (was {:?})",
-                self.current_token, other
-            )),
+            Token::Number(_) | Token::Float(_) => self.expect_number_token(),
+            _ => Err(self.unexpected_name("Number or Identifier")),
         }
     }
 
-    fn expect_operator(&mut self) -> Result<Token, String> {
+    fn expect_operator(&mut self) -> Result<Token, ParseError> {
         if matches!(
             self.current_token,
-            Token::Plus | Token::Minus | Token::Multiply | Token::Divide
+            Token::Plus
+                | Token::Minus
+                | Token::Multiply
+                | Token::Divide
+                | Token::DoubleSlash
+                | Token::Modulo
+                | Token::Caret
+                | Token::And
+                | Token::Or
+                | Token::Equal
+                | Token::NotEqual
+                | Token::Less
+                | Token::Greater
+                | Token::LessEqual
+                | Token::GreaterEqual
         ) {
             let token = self.current_token.clone();
-            self.advance();
+            self.advance()?;
             Ok(token)
         } else {
-            Err(format!("Expected operator, got {:?}", self.current_token))
+            Err(ParseError::InvalidOperator(self.current_token.clone()))
         }
     }
 
-    fn expect_token(&mut self, expected: Token) -> Result<Token, String> {
+    fn expect_token(&mut self, expected: Token) -> Result<Token, ParseError> {
         if self.current_token.clone() == expected {
-            self.advance();
+            self.advance()?;
             Ok(expected)
         } else {
-            Err(format!(
-                "Token ({:?}) did not match expected ({:?})",
-                self.current_token, expected
-            ))
+            Err(self.unexpected(format!("{:?}", expected)))
         }
     }
 
-    fn parse_primary(&mut self) -> Result<Box<ASTNode>, String> {
-        if self.current_token == Token::LParen {
-            self.advance();
-            let term = self.parse_expression()?;
-            self.expect_token(Token::RParen)?;
-            return Ok(term);
+    fn binary_op(&self, token: Token) -> Result<BinaryOperator, ParseError> {
+        token_to_binary_op(token.clone()).map_err(|_| ParseError::InvalidOperator(token))
+    }
+
+    // Everything an infix operator can apply to: a literal, variable, call,
+    // parenthesised expression, block or negation
+    fn parse_primary(&mut self) -> Result<Box<ASTNode>, ParseError> {
+        match self.current_token {
+            Token::Minus => self.parse_prefix(UnaryOperator::Negate),
+            Token::Bang => self.parse_prefix(UnaryOperator::Not),
+            Token::LParen => {
+                let open_span = self.current_span;
+                self.advance()?;
+                if self.current_token == Token::RParen {
+                    return Err(ParseError::EmptyParens(open_span));
+                }
+                let term = self.nested(Self::parse_expression)?;
+                if self.current_token != Token::RParen {
+                    return Err(ParseError::UnclosedParen(open_span));
+                }
+                self.advance()?;
+                Ok(term)
+            }
+            Token::LBrace => self.nested(Self::parse_block_expression),
+            _ => self.parse_operand(),
         }
+    }
+
+    fn parse_prefix(&mut self, op: UnaryOperator) -> Result<Box<ASTNode>, ParseError> {
+        self.advance()?;
+        Ok(boxed_node(ASTNode::UnaryOp {
+            op,
+            operand: self.nested(|parser| parser.parse_expression_bp(PREFIX_BINDING_POWER))?,
+        }))
+    }
 
+    // A literal, variable or builtin call
+    fn parse_operand(&mut self) -> Result<Box<ASTNode>, ParseError> {
+        let span = self.current_span;
         match self.expect_identifier_or_number_token()? {
-            Token::Identifier(name) => Ok(boxed_node(ASTNode::Identifier(name))),
+            Token::Identifier(name)
+                if self.current_token == Token::LParen && builtin_arity(&name).is_some() =>
+            {
+                let args = self.nested(Self::parse_call_arguments)?;
+                Ok(boxed_node(ASTNode::BuiltinCall { name, args }))
+            }
+            Token::Identifier(name) => Ok(boxed_node(ASTNode::Identifier {
+                name,
+                span: Some(span),
+            })),
             Token::Number(val) => Ok(boxed_node(ASTNode::Number(val))),
+            Token::Float(val) => Ok(boxed_node(ASTNode::Float(val))),
             other => panic!(
                 "parse_primary(): the token {:?} was not LParen, Identifier, or Number! (was {:?})",
                 self.current_token, other
@@ -99,81 +416,472 @@ impl Parser {
         }
     }
 
-    fn parse_factor(&mut self) -> Result<Box<ASTNode>, String> {
+    // `{ a = 1; a + 2 }`, statements followed by the expression the block evaluates to
+    fn parse_block_expression(&mut self) -> Result<Box<ASTNode>, ParseError> {
+        let open_span = self.current_span;
+        self.expect_token(Token::LBrace)?;
+
+        let mut statements: Vec<ASTNode> = vec![];
+        loop {
+            if self.current_token == Token::EoF {
+                return Err(ParseError::UnclosedBrace(open_span));
+            }
+            if matches!(
+                self.current_token,
+                Token::If
+                    | Token::While
+                    | Token::Read
+                    | Token::Del
+                    | Token::Let
+                    | Token::Break
+                    | Token::Continue
+                    | Token::LBrace
+            ) || self.starts_assignment()
+            {
+                statements.extend(self.parse_statement()?);
+                continue;
+            }
+
+            let expr = self.parse_expression()?;
+            if self.current_token == Token::EoF {
+                return Err(ParseError::UnclosedBrace(open_span));
+            }
+            if self.current_token != Token::Semi {
+                self.expect_token(Token::RBrace)?;
+                return Ok(boxed_node(ASTNode::BlockExpression {
+                    statements,
+                    result: expr,
+                }));
+            }
+            self.advance()?;
+            statements.push(ASTNode::ExpressionStatement(expr));
+        }
+    }
+
+    // `(a, b, ...)` after a function name, arity is left to the analyzer
+    fn parse_call_arguments(&mut self) -> Result<Vec<ASTNode>, ParseError> {
+        let open_span = self.current_span;
+        self.expect_token(Token::LParen)?;
+
+        let mut args: Vec<ASTNode> = vec![];
+        if self.current_token != Token::RParen {
+            args.push(*self.parse_expression()?);
+            while self.current_token == Token::Comma {
+                self.advance()?;
+                args.push(*self.parse_expression()?);
+            }
+        }
+
+        if self.current_token != Token::RParen {
+            return Err(ParseError::UnclosedParen(open_span));
+        }
+        self.advance()?;
+        Ok(args)
+    }
+
+    // Pratt parsing: keeps taking infix operators that bind at least as tightly as
+    // `min_bp`, parsing each right operand with the operator's right binding power
+    fn parse_expression_bp(&mut self, min_bp: u8) -> Result<Box<ASTNode>, ParseError> {
         let mut left = self.parse_primary()?;
-        while matches!(self.current_token, Token::Multiply | Token::Divide) {
-            let op_token = self.expect_operator()?;
-            match self.parse_primary() {
-                Ok(right) => {
-                    left = boxed_node(ASTNode::BinaryOp {
-                        left: left.clone(),
-                        op: token_to_binary_op(op_token)?,
-                        right,
-                    });
-                }
-                Err(err) => {
-                    println!("parse_factor(): {}", err);
-                    return Ok(left);
-                }
-            };
+        let mut height = None;
+        while let Some((left_bp, right_bp)) = infix_binding_power(&self.current_token) {
+            if left_bp < min_bp {
+                break;
+            }
+            left = self.parse_infix(left, right_bp)?;
+            height = Some(self.check_fold_height(&left, height)?);
         }
 
         Ok(left)
     }
 
-    fn parse_term(&mut self) -> Result<Box<ASTNode>, String> {
-        let mut left = self.parse_factor()?;
-        while matches!(self.current_token, Token::Plus | Token::Minus) {
-            let op_token = self.expect_operator()?;
-            match self.parse_factor() {
-                Ok(right) => {
-                    left = boxed_node(ASTNode::BinaryOp {
-                        left: left.clone(),
-                        op: token_to_binary_op(op_token)?,
-                        right,
-                    });
-                }
-                Err(err) => {
-                    println!("parse_term(): {}", err);
-                    return Ok(left);
-                }
-            };
+    // Each fold puts the chain so far one level deeper, so the height of the tree
+    // is tracked as it grows. `height` is the left operand's, if already known.
+    fn check_fold_height(
+        &self,
+        node: &ASTNode,
+        height: Option<usize>,
+    ) -> Result<usize, ParseError> {
+        let ASTNode::BinaryOp { left, right, .. } = node else {
+            return Ok(tree_height(node));
+        };
+        let height = height
+            .unwrap_or_else(|| tree_height(left))
+            .max(tree_height(right))
+            + 1;
+        if self.depth + height > MAX_EXPRESSION_DEPTH {
+            return Err(ParseError::ExpressionTooDeep(MAX_EXPRESSION_DEPTH));
         }
+        Ok(height)
+    }
 
-        Ok(left)
+    // Comparisons don't chain, `a < b < c` is a parse error
+    fn parse_infix(
+        &mut self,
+        left: Box<ASTNode>,
+        right_bp: u8,
+    ) -> Result<Box<ASTNode>, ParseError> {
+        let comparison = self.at_comparison();
+        let op_token = self.expect_operator()?;
+        let op = self.binary_op(op_token)?;
+        let right = self.nested(|parser| parser.parse_expression_bp(right_bp))?;
+
+        if comparison && self.at_comparison() {
+            return Err(ParseError::ChainedComparison {
+                first: op,
+                second: self.binary_op(self.current_token.clone())?,
+                span: self.current_span,
+            });
+        }
+
+        Ok(boxed_node(ASTNode::BinaryOp { left, op, right }))
     }
 
-    fn parse_expression(&mut self) -> Result<Box<ASTNode>, String> {
-        self.parse_term()
+    fn at_comparison(&self) -> bool {
+        infix_binding_power(&self.current_token) == Some(COMPARISON_BINDING_POWER)
+    }
+
+    // `?:` has the lowest precedence and nests to the right, so
+    // `a ? b : c ? d : e` is `a ? b : (c ? d : e)`
+    fn parse_expression(&mut self) -> Result<Box<ASTNode>, ParseError> {
+        let condition = self.parse_expression_bp(0)?;
+        if self.current_token != Token::Question {
+            return Ok(condition);
+        }
+        self.parse_ternary(condition)
     }
 
-    fn parse_assignment(&mut self) -> Result<ASTNode, String> {
+    fn parse_ternary(&mut self, condition: Box<ASTNode>) -> Result<Box<ASTNode>, ParseError> {
+        self.advance()?;
+        let then_value = self.nested(Self::parse_expression)?;
+        self.expect_token(Token::Colon)?;
+        let else_value = self.nested(Self::parse_expression)?;
+        Ok(boxed_node(ASTNode::Ternary {
+            condition,
+            then_value,
+            else_value,
+        }))
+    }
+
+    fn parse_assignment(&mut self) -> Result<ASTNode, ParseError> {
+        let ident_span = self.current_span;
         let ident_token = self.expect_identifier_token()?;
         let ident = match ident_token {
             Token::Identifier(name) => name,
             _ => panic!("Expected identifier, got {:?}", ident_token),
         };
 
-        self.expect_token(Token::Assign)?;
+        if self.current_token == Token::Colon {
+            self.advance()?;
+            let declared_type = self.parse_type_name()?;
+            self.expect_token(Token::Assign)?;
+            return Ok(ASTNode::Assignment {
+                variable: ident,
+                declared_type: Some(declared_type),
+                constant: false,
+                declaration: false,
+                value: self.parse_assigned_value()?,
+                span: Some(ident_span),
+            });
+        }
 
-        let expr = self.parse_expression()?;
+        // `x++` and `x--` desugar into `x = x + 1` and `x = x - 1`
+        let step_op = match self.current_token {
+            Token::PlusPlus => Some(BinaryOperator::Add),
+            Token::MinusMinus => Some(BinaryOperator::Subtract),
+            _ => None,
+        };
+        if let Some(op) = step_op {
+            self.advance()?;
+            return Ok(ASTNode::Assignment {
+                variable: ident.clone(),
+                declared_type: None,
+                constant: false,
+                declaration: false,
+                value: boxed_node(ASTNode::BinaryOp {
+                    left: boxed_node(ASTNode::Identifier {
+                        name: ident,
+                        span: Some(ident_span),
+                    }),
+                    op,
+                    right: boxed_node(ASTNode::Number(1.0)),
+                }),
+                span: Some(ident_span),
+            });
+        }
 
-        let _ = self.expect_token(Token::Semi)?;
+        // Compound assignments desugar `x += e` into `x = x + (e)`
+        let compound_op = match self.current_token {
+            Token::PlusAssign => Some(BinaryOperator::Add),
+            Token::MinusAssign => Some(BinaryOperator::Subtract),
+            Token::MultiplyAssign => Some(BinaryOperator::Multiply),
+            Token::DivideAssign => Some(BinaryOperator::Divide),
+            Token::ModuloAssign => Some(BinaryOperator::Modulo),
+            _ => None,
+        };
+
+        let Some(op) = compound_op else {
+            self.expect_token(Token::Assign)?;
+            return Ok(ASTNode::Assignment {
+                variable: ident,
+                declared_type: None,
+                constant: false,
+                declaration: false,
+                value: self.parse_assigned_value()?,
+                span: Some(ident_span),
+            });
+        };
 
+        self.advance()?;
+        let expr = self.parse_expression()?;
         Ok(ASTNode::Assignment {
-            variable: ident,
-            value: expr,
+            variable: ident.clone(),
+            declared_type: None,
+            constant: false,
+            declaration: false,
+            value: boxed_node(ASTNode::BinaryOp {
+                left: boxed_node(ASTNode::Identifier {
+                    name: ident,
+                    span: Some(ident_span),
+                }),
+                op,
+                right: expr,
+            }),
+            span: Some(ident_span),
         })
     }
 
-    fn parse_statement(&mut self) -> Result<ASTNode, String> {
-        self.parse_assignment()
+    // The right side of `=`. In `a = b = 5` the assignment to `b` is nested as the value of `a`.
+    fn parse_assigned_value(&mut self) -> Result<Box<ASTNode>, ParseError> {
+        let value = self.parse_expression()?;
+        match value.as_ref() {
+            ASTNode::Identifier { name, span } if self.current_token == Token::Assign => {
+                let (variable, span) = (name.clone(), *span);
+                self.advance()?;
+                Ok(boxed_node(ASTNode::Assignment {
+                    variable,
+                    declared_type: None,
+                    constant: false,
+                    declaration: false,
+                    value: self.nested(Self::parse_assigned_value)?,
+                    span,
+                }))
+            }
+            _ => Ok(value),
+        }
+    }
+
+    fn parse_type_name(&mut self) -> Result<Type, ParseError> {
+        let declared_type = match &self.current_token {
+            Token::Identifier(name) if name == "int" => Type::Integer,
+            Token::Identifier(name) if name == "float" => Type::Float,
+            Token::Identifier(name) if name == "bool" => Type::Boolean,
+            _ => return Err(self.unexpected("Type name (int, float, bool)")),
+        };
+        self.advance()?;
+        Ok(declared_type)
+    }
+
+    // `x = 1, y = 2;` is shorthand for separate statements sharing one semicolon
+    fn parse_assignment_list(&mut self) -> Result<Vec<ASTNode>, ParseError> {
+        let mut assignments = vec![self.parse_assignment()?];
+        while self.current_token == Token::Comma {
+            self.advance()?;
+            assignments.push(self.parse_assignment()?);
+        }
+
+        self.expect_statement_end()?;
+        Ok(assignments)
+    }
+
+    // The last statement of the input may leave out its `;`
+    fn expect_statement_end(&mut self) -> Result<(), ParseError> {
+        if self.current_token != Token::EoF {
+            self.expect_token(Token::Semi)?;
+        }
+        Ok(())
+    }
+
+    fn parse_condition(&mut self) -> Result<Box<ASTNode>, ParseError> {
+        self.expect_token(Token::LParen)?;
+        let condition = self.parse_expression()?;
+        self.expect_token(Token::RParen)?;
+        Ok(condition)
+    }
+
+    fn parse_block(&mut self) -> Result<ASTNode, ParseError> {
+        let open_span = self.current_span;
+        self.expect_token(Token::LBrace)?;
+
+        let mut statements: Vec<ASTNode> = vec![];
+        while !matches!(self.current_token, Token::RBrace | Token::EoF) {
+            statements.extend(self.nested(Self::parse_statement)?);
+        }
+
+        if self.current_token == Token::EoF {
+            return Err(ParseError::UnclosedBrace(open_span));
+        }
+        self.advance()?;
+        Ok(ASTNode::Block(statements))
+    }
+
+    fn parse_if(&mut self) -> Result<ASTNode, ParseError> {
+        self.expect_token(Token::If)?;
+        let condition = self.parse_condition()?;
+        let then_branch = boxed_node(self.parse_block()?);
+
+        let else_branch = if self.current_token == Token::Else {
+            self.advance()?;
+            if self.current_token == Token::If {
+                Some(boxed_node(self.nested(Self::parse_if)?))
+            } else {
+                Some(boxed_node(self.parse_block()?))
+            }
+        } else {
+            None
+        };
+
+        Ok(ASTNode::If {
+            condition,
+            then_branch,
+            else_branch,
+        })
+    }
+
+    fn parse_while(&mut self) -> Result<ASTNode, ParseError> {
+        self.expect_token(Token::While)?;
+        let condition = self.parse_condition()?;
+        let body = boxed_node(self.parse_block()?);
+
+        Ok(ASTNode::While { condition, body })
+    }
+
+    // `read x;`
+    fn parse_read(&mut self) -> Result<ASTNode, ParseError> {
+        self.expect_token(Token::Read)?;
+        let variable = match self.expect_identifier_token()? {
+            Token::Identifier(name) => name,
+            other => panic!("Expected identifier, got {:?}", other),
+        };
+        self.expect_statement_end()?;
+        Ok(ASTNode::Read(variable))
+    }
+
+    // `del x;`
+    fn parse_delete(&mut self) -> Result<ASTNode, ParseError> {
+        self.expect_token(Token::Del)?;
+        let variable = match self.expect_identifier_token()? {
+            Token::Identifier(name) => name,
+            other => panic!("Expected identifier, got {:?}", other),
+        };
+        self.expect_statement_end()?;
+        Ok(ASTNode::Delete(variable))
+    }
+
+    // `break;` or `continue;`
+    fn parse_loop_control(&mut self) -> Result<ASTNode, ParseError> {
+        let node = match self.current_token {
+            Token::Break => ASTNode::Break,
+            _ => ASTNode::Continue,
+        };
+        self.advance()?;
+        self.expect_statement_end()?;
+        Ok(node)
+    }
+
+    // `3 + 4;` is evaluated and its value discarded
+    fn parse_expression_statement(&mut self) -> Result<ASTNode, ParseError> {
+        let expr = self.parse_expression()?;
+        self.expect_statement_end()?;
+        Ok(ASTNode::ExpressionStatement(expr))
+    }
+
+    fn starts_assignment(&self) -> bool {
+        matches!(self.current_token, Token::Identifier(_)) && self.next_is_assignment_operator()
+    }
+
+    fn next_is_assignment_operator(&self) -> bool {
+        matches!(
+            self.next_token,
+            Ok(Token::Assign
+                | Token::PlusAssign
+                | Token::MinusAssign
+                | Token::MultiplyAssign
+                | Token::DivideAssign
+                | Token::ModuloAssign
+                | Token::PlusPlus
+                | Token::MinusMinus
+                | Token::Colon)
+        )
+    }
+
+    // `const x = value;` or `const x: type = value;`
+    fn parse_const(&mut self) -> Result<ASTNode, ParseError> {
+        self.expect_token(Token::Const)?;
+        if !matches!(self.next_token, Ok(Token::Assign | Token::Colon)) {
+            self.expect_identifier_token()?;
+            return Err(self.unexpected("Assign"));
+        }
+
+        let mut assignment = self.parse_assignment()?;
+        if let ASTNode::Assignment { constant, .. } = &mut assignment {
+            *constant = true;
+        }
+        self.expect_statement_end()?;
+        Ok(assignment)
+    }
+
+    // `let x = value;` or `let x: type = value;`
+    fn parse_let(&mut self) -> Result<ASTNode, ParseError> {
+        self.expect_token(Token::Let)?;
+        if !matches!(self.next_token, Ok(Token::Assign | Token::Colon)) {
+            self.expect_identifier_token()?;
+            return Err(self.unexpected("Assign"));
+        }
+
+        let mut assignment = self.parse_assignment()?;
+        if let ASTNode::Assignment { declaration, .. } = &mut assignment {
+            *declaration = true;
+        }
+        self.expect_statement_end()?;
+        Ok(assignment)
+    }
+
+    fn parse_statement(&mut self) -> Result<Vec<ASTNode>, ParseError> {
+        // `if = 5;` is a misused name rather than a malformed if statement
+        if keyword_text(&self.current_token).is_some() && self.next_is_assignment_operator() {
+            return Err(self.unexpected_name("Identifier"));
+        }
+
+        match self.current_token {
+            Token::Const => Ok(vec![self.parse_const()?]),
+            Token::Let => Ok(vec![self.parse_let()?]),
+            Token::Break | Token::Continue => Ok(vec![self.parse_loop_control()?]),
+            Token::If => Ok(vec![self.parse_if()?]),
+            Token::While => Ok(vec![self.parse_while()?]),
+            Token::Read => Ok(vec![self.parse_read()?]),
+            Token::Del => Ok(vec![self.parse_delete()?]),
+            Token::LBrace => Ok(vec![self.parse_block()?]),
+            _ if self.starts_assignment() => self.parse_assignment_list(),
+            _ => Ok(vec![self.parse_expression_statement()?]),
+        }
+    }
+
+    pub fn parse_single_expression(&mut self) -> Result<ASTNode, ParseError> {
+        self.check_initial_error()?;
+        let expr = self.parse_expression()?;
+        if self.current_token != Token::EoF {
+            return Err(self.unexpected("EoF"));
+        }
+
+        Ok(*expr)
     }
 
-    pub fn parse_program(&mut self) -> Result<ASTNode, String> {
+    pub fn parse_program(&mut self) -> Result<ASTNode, ParseError> {
+        self.check_initial_error()?;
         let mut program_vec: Vec<ASTNode> = vec![];
         while self.current_token != Token::EoF {
-            program_vec.push(self.parse_statement()?);
+            program_vec.extend(self.parse_statement()?);
         }
 
         Ok(ASTNode::Program(program_vec))
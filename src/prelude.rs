@@ -0,0 +1,27 @@
+use std::f64::consts::{E, PI, TAU};
+
+// Constants available to scripts without declaring them, when the prelude is enabled
+pub const PRELUDE_CONSTANTS: [(&str, f64); 4] =
+    [("pi", PI), ("e", E), ("tau", TAU), ("inf", f64::INFINITY)];
+
+// Functions that are always callable, with the number of arguments each takes
+// `print` writes its argument on its own line and evaluates to it
+pub const BUILTIN_FUNCTIONS: [(&str, usize); 10] = [
+    ("sqrt", 1),
+    ("abs", 1),
+    ("pow", 2),
+    ("min", 2),
+    ("max", 2),
+    ("sin", 1),
+    ("cos", 1),
+    ("exp", 1),
+    ("ln", 1),
+    ("print", 1),
+];
+
+pub fn builtin_arity(name: &str) -> Option<usize> {
+    BUILTIN_FUNCTIONS
+        .iter()
+        .find(|(builtin, _)| *builtin == name)
+        .map(|(_, arity)| *arity)
+}
@@ -1,9 +1,14 @@
-use crate::ast::ASTNode;
-use std::{collections::HashMap, mem};
+use crate::ast::{evaluate_constant, node_span, ASTNode, BinaryOperator, UnaryOperator};
+use crate::lexer::{keyword_token, Span};
+use crate::prelude::{builtin_arity, PRELUDE_CONSTANTS};
+use std::collections::{HashMap, HashSet};
 
 #[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Type {
     Integer,
+    Float,
+    Boolean,
     Function,
     // Add more types as needed
 }
@@ -13,13 +18,35 @@ pub struct Symbol {
     pub name: String,
     pub symbol_type: Type,
     pub scope_level: usize,
+    // Where the declaring assignment was written, if known
+    pub declared_at: Option<Span>,
+    // False for `const` bindings
+    pub mutable: bool,
+    // False until the variable is assigned, for symbols from declare_uninitialized
+    pub initialized: bool,
+    // True for `x: int = ...`, whose type can't widen to Float on reassignment
+    pub annotated: bool,
 }
 
+#[derive(Clone)]
 pub struct SymbolTable {
     scopes: Vec<HashMap<String, Symbol>>,
     current_scope: usize,
 }
 
+// A saved copy of every scope, taken before speculative analysis so it can be undone
+#[derive(Clone)]
+pub struct SymbolTableSnapshot {
+    scopes: Vec<HashMap<String, Symbol>>,
+    current_scope: usize,
+}
+
+impl Default for SymbolTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl SymbolTable {
     pub fn new() -> Self {
         let scopes: Vec<HashMap<String, Symbol>> = vec![HashMap::new()];
@@ -30,31 +57,66 @@ impl SymbolTable {
         }
     }
 
+    // The innermost scope is always the last one, so a scope is created fresh
+    // on every enter and dropped with all its entries on exit
     pub fn enter_scope(&mut self) {
-        self.current_scope += 1;
-        while self.scopes.len() <= self.current_scope {
-            self.scopes.push(HashMap::new());
+        self.scopes.push(HashMap::new());
+        self.current_scope = self.scopes.len() - 1;
+    }
+
+    // 0 is the global scope
+    pub fn current_scope(&self) -> usize {
+        self.current_scope
+    }
+
+    pub fn snapshot(&self) -> SymbolTableSnapshot {
+        SymbolTableSnapshot {
+            scopes: self.scopes.clone(),
+            current_scope: self.current_scope,
         }
     }
 
-    pub fn exit_scope(&mut self) {
+    // Puts back the scope stack exactly as it was when the snapshot was taken
+    pub fn restore(&mut self, snapshot: SymbolTableSnapshot) {
+        self.scopes = snapshot.scopes;
+        self.current_scope = snapshot.current_scope;
+    }
+
+    pub fn exit_scope(&mut self) -> Result<(), String> {
         if self.current_scope == 0 {
-            panic!("Attempting to pop the final scope!")
+            return Err("Attempting to pop the final scope!".to_string());
         }
 
-        while self.scopes.len() > self.current_scope {
-            let _ = self.scopes.pop();
-        }
-        self.current_scope -= 1;
+        self.scopes.truncate(self.current_scope);
+        self.current_scope = self.scopes.len() - 1;
+        Ok(())
     }
 
     pub fn declare_variable(&mut self, name: &String, var_type: Type) -> Result<(), String> {
+        self.declare_variable_at(name, var_type, None, true)
+    }
+
+    // Duplicate declarations are reported with both positions when they are known
+    pub fn declare_variable_at(
+        &mut self,
+        name: &String,
+        var_type: Type,
+        declared_at: Option<Span>,
+        mutable: bool,
+    ) -> Result<(), String> {
         let scope = &mut self.scopes[self.current_scope];
-        if scope.contains_key(name) {
-            return Err(format!(
-                "Trying to declare a duplicate variable \"{}\"",
-                name
-            ));
+        if let Some(existing) = scope.get(name) {
+            let mut message = format!("Trying to declare a duplicate variable \"{}\"", name);
+            if let Some(span) = declared_at {
+                message += &format!(" at line {}, column {}", span.line, span.column);
+            }
+            if let Some(span) = existing.declared_at {
+                message += &format!(
+                    ", first declared at line {}, column {}",
+                    span.line, span.column
+                );
+            }
+            return Err(message);
         }
 
         scope.insert(
@@ -63,131 +125,733 @@ impl SymbolTable {
                 name: name.to_string(),
                 symbol_type: var_type,
                 scope_level: self.current_scope,
+                declared_at,
+                mutable,
+                initialized: true,
+                annotated: false,
             },
         );
 
         Ok(())
     }
 
-    pub fn lookup_variable(&self, name: &str) -> Option<&Symbol> {
-        let mut current_level = self.current_scope;
-        while !self.scopes[current_level].contains_key(name) && current_level > 0 {
-            current_level -= 1;
+    // A variable whose value comes later, reading it before an assignment is an error
+    pub fn declare_uninitialized(&mut self, name: &String, var_type: Type) -> Result<(), String> {
+        self.declare_variable(name, var_type)?;
+        if let Some(symbol) = self.scopes[self.current_scope].get_mut(name) {
+            symbol.initialized = false;
+        }
+        Ok(())
+    }
+
+    // Updates an already declared variable, rejecting values of a different type.
+    // An Integer that wasn't annotated widens to Float, so `x = 8; x /= 4;` works.
+    pub fn assign_variable(&mut self, name: &str, var_type: Type) -> Result<(), String> {
+        let Some(symbol) = self.lookup_variable_mut(name) else {
+            return Err(format!(
+                "Trying to assign to undeclared variable \"{}\"",
+                name
+            ));
+        };
+
+        let widens =
+            symbol.symbol_type == Type::Integer && var_type == Type::Float && !symbol.annotated;
+        if symbol.symbol_type != var_type && !widens {
+            return Err(format!(
+                "Cannot assign a value of type {:?} to \"{}\" of type {:?}",
+                var_type, name, symbol.symbol_type
+            ));
+        }
+        symbol.symbol_type = var_type;
+        symbol.initialized = true;
+        Ok(())
+    }
+
+    // Removes the declaration `name` currently refers to, uncovering any it shadowed
+    pub fn remove_variable(&mut self, name: &str) -> Result<(), String> {
+        self.scopes[..=self.current_scope]
+            .iter_mut()
+            .rev()
+            .find_map(|scope| scope.remove(name))
+            .map(|_| ())
+            .ok_or_else(|| format!("Trying to delete undeclared variable \"{}\"", name))
+    }
+
+    // Names whose visible declaration has been assigned a value
+    fn initialized_names(&self) -> HashSet<String> {
+        self.scopes[..=self.current_scope]
+            .iter()
+            .flat_map(|scope| scope.keys())
+            .filter(|name| self.lookup_variable(name).is_some_and(|x| x.initialized))
+            .cloned()
+            .collect()
+    }
+
+    // Marks the visible declaration of `name` as possibly never assigned
+    fn set_uninitialized(&mut self, name: &str) {
+        if let Some(symbol) = self.lookup_variable_mut(name) {
+            symbol.initialized = false;
         }
+    }
+
+    fn lookup_variable_mut(&mut self, name: &str) -> Option<&mut Symbol> {
+        self.scopes[..=self.current_scope]
+            .iter_mut()
+            .rev()
+            .find_map(|scope| scope.get_mut(name))
+    }
 
-        self.scopes[current_level].get(name)
+    // Searches from the innermost scope outwards, so shadowing declarations win
+    pub fn lookup_variable(&self, name: &str) -> Option<&Symbol> {
+        self.scopes[..=self.current_scope]
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(name))
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct SemanticError {
     pub message: String,
     pub error_type: SemanticErrorType,
+    pub span: Option<Span>,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum SemanticErrorType {
     UndefinedVariable,
     DuplicateDeclaration,
     TypeMismatch,
+    DisallowedOperator,
+    DivisionByZero,
+    ArityMismatch,
+    AssignToConst,
+    ReservedKeyword,
+    UninitializedVariable,
+    // Strict mode only, a plain `x = ...` to a variable no `let` has declared
+    UndeclaredAssignment,
+    // A `break` or `continue` that isn't inside a loop body
+    LoopControlOutsideLoop,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum SemanticWarning {
+    // An if/while condition that always evaluates the same way
+    ConstantCondition(ASTNode),
+    // A variable that is assigned but never read
+    UnusedVariable(String),
+    // A declaration hiding a variable of the same name from an enclosing scope
+    Shadowing {
+        name: String,
+        outer_scope: usize,
+        inner_scope: usize,
+    },
 }
 
 pub struct SemanticAnalyzer {
     pub symbol_table: SymbolTable,
+    // Errors from every analysis since the last clear_errors()
     errors: Vec<SemanticError>,
+    warnings: Vec<SemanticWarning>,
+    // None allows every operator
+    allowed_operators: Option<HashSet<BinaryOperator>>,
+    // Variables declared during the current analysis, in declaration order
+    declared: Vec<String>,
+    read: HashSet<String>,
+    warn_shadowing: bool,
+    // Plain assignments may only update variables, declaring needs `let`
+    strict: bool,
+    // How many loop bodies the node being visited is inside of
+    loop_depth: usize,
+    // Span of the innermost assignment being visited, for nodes that have none
+    current_span: Option<Span>,
+}
+
+impl Default for SemanticAnalyzer {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl SemanticAnalyzer {
     pub fn new() -> Self {
         let symbol_table = SymbolTable::new();
         let errors: Vec<SemanticError> = vec![];
+        let warnings: Vec<SemanticWarning> = vec![];
         SemanticAnalyzer {
             symbol_table,
             errors,
+            warnings,
+            allowed_operators: None,
+            declared: vec![],
+            read: HashSet::new(),
+            warn_shadowing: true,
+            strict: false,
+            loop_depth: 0,
+            current_span: None,
+        }
+    }
+
+    // Analyzer that rejects any binary operator not in `operators`
+    pub fn with_allowed_operators(operators: HashSet<BinaryOperator>) -> Self {
+        let mut analyzer = SemanticAnalyzer::new();
+        analyzer.allowed_operators = Some(operators);
+        analyzer
+    }
+
+    // Analyzer whose global scope already contains the prelude constants, which
+    // can't be reassigned
+    pub fn with_prelude() -> Self {
+        let mut analyzer = SemanticAnalyzer::new();
+        for (name, _) in PRELUDE_CONSTANTS {
+            _ = analyzer.symbol_table.declare_variable_at(
+                &name.to_string(),
+                Type::Float,
+                None,
+                false,
+            );
+        }
+        analyzer
+    }
+
+    // Analyzer for programs whose `inputs` are seeded into storage before running,
+    // e.g. with VM::execute_with_inputs
+    pub fn with_inputs(inputs: &[(&str, Type)]) -> Self {
+        let mut analyzer = SemanticAnalyzer::new();
+        for (name, input_type) in inputs {
+            _ = analyzer
+                .symbol_table
+                .declare_variable(&name.to_string(), input_type.clone());
         }
+        analyzer
+    }
+
+    // Shadowing warnings are on by default
+    pub fn set_shadowing_warnings(&mut self, enabled: bool) {
+        self.warn_shadowing = enabled;
+    }
+
+    // Strict mode is off by default, so `x = 5;` declares `x` if needed
+    pub fn set_strict_mode(&mut self, enabled: bool) {
+        self.strict = enabled;
+    }
+
+    // Warnings from the most recent call to analyze()
+    pub fn warnings(&self) -> &[SemanticWarning] {
+        &self.warnings
+    }
+
+    pub fn errors(&self) -> &[SemanticError] {
+        &self.errors
+    }
+
+    pub fn clear_errors(&mut self) {
+        self.errors.clear();
     }
 
+    // Returns only the errors found in `ast`, earlier ones stay in errors() until cleared
     pub fn analyze(&mut self, ast: &ASTNode) -> Result<(), Vec<SemanticError>> {
+        self.warnings.clear();
+        self.declared.clear();
+        self.read.clear();
+        self.loop_depth = 0;
+
+        let first_new = self.errors.len();
         self.visit_node(ast);
-        if self.errors.is_empty() {
-            Ok(())
-        } else {
-            // Empty the self.errors vector since we won't need it after returning them
-            Err(mem::take(&mut self.errors))
+        match &self.errors[first_new..] {
+            [] => Ok(()),
+            new_errors => Err(new_errors.to_vec()),
         }
     }
 
-    fn visit_node(&mut self, node: &ASTNode) {
+    // Returns the type of the value an expression evaluates to, None for statements
+    // and expressions whose type is unknown because an error was already reported
+    fn visit_node(&mut self, node: &ASTNode) -> Option<Type> {
         match node {
             ASTNode::Program(statements) => {
                 for node in statements {
                     self.visit_node(node);
                 }
+                self.warn_unused_variables();
+            }
+            // A chained assignment takes the type of the variable it assigned
+            ASTNode::Assignment {
+                variable,
+                declared_type,
+                constant,
+                declaration,
+                value,
+                span,
+            } => {
+                self.visit_assignment(
+                    variable,
+                    declared_type.as_ref(),
+                    *constant,
+                    *declaration,
+                    value,
+                    *span,
+                );
+                return self.variable_type(variable);
+            }
+            ASTNode::UnaryOp { op, operand } => return self.visit_unary_op(op, operand),
+            ASTNode::BuiltinCall { name, args } => return self.visit_builtin_call(name, args),
+            ASTNode::BinaryOp { left, op, right } => return self.visit_binary_op(left, op, right),
+            ASTNode::Identifier { name, span } => return self.visit_identifier(name, *span),
+            ASTNode::Ternary {
+                condition,
+                then_value,
+                else_value,
+            } => return self.visit_ternary(condition, then_value, else_value),
+            ASTNode::Read(variable) => self.visit_read(variable),
+            ASTNode::Delete(variable) => self.visit_delete(variable),
+            ASTNode::ExpressionStatement(expr) => {
+                self.visit_node(expr);
+            }
+            ASTNode::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => self.visit_if(condition, then_branch, else_branch.as_deref()),
+            ASTNode::While { condition, body } => {
+                self.visit_condition(condition);
+                // The body may never run, so nothing it assigns is certain afterwards
+                let before = self.symbol_table.initialized_names();
+                self.loop_depth += 1;
+                self.visit_node(body);
+                self.loop_depth -= 1;
+                for name in self.symbol_table.initialized_names().difference(&before) {
+                    self.symbol_table.set_uninitialized(name);
+                }
             }
-            ASTNode::Assignment { variable, value } => {
-                let var_type = match self.get_expression_type(value) {
-                    Some(x) => x,
-                    None => {
-                        panic!(
-                            "Unable to get expression type of {} ({:?})",
-                            variable, value
-                        )
-                    }
-                };
-
-                match self.symbol_table.declare_variable(variable, var_type) {
-                    Err(msg) => self.add_error(msg, SemanticErrorType::DuplicateDeclaration),
-                    _ => (),
+            ASTNode::Break | ASTNode::Continue => self.visit_loop_control(node),
+            ASTNode::Block(statements) => {
+                for node in statements {
+                    self.visit_node(node);
                 }
             }
-            ASTNode::BinaryOp { left, op: _, right } => {
-                self.visit_node(left);
-                self.visit_node(right);
-
-                if self.get_expression_type(left) != self.get_expression_type(right) {
-                    self.add_error(
-                        format!("Type mismatch between operands:\n{:?} | {:?}", left, right),
-                        SemanticErrorType::TypeMismatch,
-                    );
+            ASTNode::BlockExpression { statements, result } => {
+                // Jumping out would leave the enclosing expression half evaluated
+                let loop_depth = std::mem::replace(&mut self.loop_depth, 0);
+                self.symbol_table.enter_scope();
+                for node in statements {
+                    self.visit_node(node);
                 }
+                // Typed while the block's own variables are still in scope
+                let result_type = self.visit_node(result);
+                _ = self.symbol_table.exit_scope();
+                self.loop_depth = loop_depth;
+                return result_type;
+            }
+            ASTNode::Number(_) => return Some(Type::Integer),
+            ASTNode::Float(_) => return Some(Type::Float),
+        }
+        None
+    }
+
+    fn visit_builtin_call(&mut self, name: &str, args: &[ASTNode]) -> Option<Type> {
+        let mut arg_types = Vec::with_capacity(args.len());
+        for arg in args {
+            arg_types.push(self.visit_node(arg));
+        }
+
+        let expected = builtin_arity(name).unwrap_or_default();
+        if args.len() != expected {
+            self.add_error(
+                format!(
+                    "{} takes {} argument(s) but was given {}",
+                    name,
+                    expected,
+                    args.len()
+                ),
+                SemanticErrorType::ArityMismatch,
+                None,
+            );
+        }
+
+        // `max(3, 7.5)` is promoted like `3 + 7.5`
+        match name {
+            "pow" | "sqrt" | "sin" | "cos" | "exp" | "ln" => Some(Type::Float),
+            _ => arg_types.into_iter().reduce(promote).flatten(),
+        }
+    }
+
+    fn visit_binary_op(
+        &mut self,
+        left: &ASTNode,
+        op: &BinaryOperator,
+        right: &ASTNode,
+    ) -> Option<Type> {
+        let left_type = self.visit_node(left);
+        let right_type = self.visit_node(right);
+
+        // Divisors that fold to zero can be caught before running, variables are left to the VM
+        if matches!(
+            op,
+            BinaryOperator::Divide | BinaryOperator::FloorDiv | BinaryOperator::Modulo
+        ) && evaluate_constant(right) == Some(0.0)
+        {
+            self.add_error(
+                "Division by zero".to_string(),
+                SemanticErrorType::DivisionByZero,
+                node_span(right).or(self.current_span),
+            );
+        }
+
+        if let Some(allowed) = &self.allowed_operators {
+            if !allowed.contains(op) {
+                self.add_error(
+                    format!("Operator {:?} is not allowed", op),
+                    SemanticErrorType::DisallowedOperator,
+                    None,
+                );
+            }
+        }
+
+        // An exponent can be any number, and an integer mixed with a float or a
+        // comparison result is promoted rather than rejected.
+        // `&&` and `||` only test their operands against zero, so any types mix.
+        if !matches!(
+            op,
+            BinaryOperator::Power | BinaryOperator::And | BinaryOperator::Or
+        ) && left_type != right_type
+            && !(is_numeric(&left_type) && is_numeric(&right_type))
+        {
+            self.add_error(
+                format!("Type mismatch between operands:\n{:?} | {:?}", left, right),
+                SemanticErrorType::TypeMismatch,
+                None,
+            );
+        }
+        binary_result_type(op, left_type, right_type, right)
+    }
+
+    // A variable is only initialized after an if when every branch assigns it
+    fn visit_if(
+        &mut self,
+        condition: &ASTNode,
+        then_branch: &ASTNode,
+        else_branch: Option<&ASTNode>,
+    ) {
+        self.visit_condition(condition);
+        let before = self.symbol_table.initialized_names();
+        self.visit_node(then_branch);
+
+        // The else branch runs without anything the then branch assigned
+        let after_then = self.symbol_table.initialized_names();
+        for name in after_then.difference(&before) {
+            self.symbol_table.set_uninitialized(name);
+        }
+        if let Some(else_branch) = else_branch {
+            self.visit_node(else_branch);
+        }
+
+        for name in self.symbol_table.initialized_names() {
+            if !before.contains(&name) && !after_then.contains(&name) {
+                self.symbol_table.set_uninitialized(&name);
+            }
+        }
+    }
+
+    fn visit_loop_control(&mut self, node: &ASTNode) {
+        if self.loop_depth == 0 {
+            let keyword = match node {
+                ASTNode::Break => "break",
+                _ => "continue",
+            };
+            self.add_error(
+                format!("`{}` can only be used inside a loop", keyword),
+                SemanticErrorType::LoopControlOutsideLoop,
+                None,
+            );
+        }
+    }
+
+    fn visit_assignment(
+        &mut self,
+        variable: &String,
+        declared_type: Option<&Type>,
+        constant: bool,
+        declaration: bool,
+        value: &ASTNode,
+        span: Option<Span>,
+    ) {
+        let outer_span = self.current_span;
+        self.current_span = span.or(outer_span);
+        let value_type = self.visit_node(value);
+        self.current_span = outer_span;
+
+        // Annotated assignments always declare, using the annotated type
+        if let Some(declared_type) = declared_type {
+            match value_type {
+                Some(value_type) if value_type != *declared_type => self.add_error(
+                    format!(
+                        "Cannot initialize \"{}\" of type {:?} with a value of type {:?}",
+                        variable, declared_type, value_type
+                    ),
+                    SemanticErrorType::TypeMismatch,
+                    None,
+                ),
+                _ => {}
+            }
+            self.declare(variable, declared_type.clone(), span, !constant);
+            if let Some(symbol) = self.symbol_table.lookup_variable_mut(variable) {
+                symbol.annotated = true;
+            }
+            return;
+        }
+
+        // An undefined operand has already been reported by visiting the value
+        let Some(var_type) = value_type else {
+            return;
+        };
+
+        let existing = self.symbol_table.lookup_variable(variable);
+        if existing.is_none() && !constant && !declaration && self.strict {
+            self.add_error(
+                format!(
+                    "Assignment to undeclared variable \"{}\", declare it with `let {} = ...`",
+                    variable, variable
+                ),
+                SemanticErrorType::UndeclaredAssignment,
+                span,
+            );
+        } else if constant || declaration || existing.is_none() {
+            self.declare(variable, var_type, span, !constant);
+        } else if existing.is_some_and(|symbol| !symbol.mutable) {
+            self.add_error(
+                format!("Cannot assign to constant \"{}\"", variable),
+                SemanticErrorType::AssignToConst,
+                span,
+            );
+        } else if let Err(msg) = self.symbol_table.assign_variable(variable, var_type) {
+            self.add_error(msg, SemanticErrorType::TypeMismatch, None);
+        }
+    }
+
+    fn declare(&mut self, variable: &String, var_type: Type, span: Option<Span>, mutable: bool) {
+        if keyword_token(variable).is_some() {
+            return self.add_error(
+                format!(
+                    "\"{}\" is a reserved keyword and cannot be declared",
+                    variable
+                ),
+                SemanticErrorType::ReservedKeyword,
+                span,
+            );
+        }
+
+        let inner_scope = self.symbol_table.current_scope();
+        let shadowed = self
+            .symbol_table
+            .lookup_variable(variable)
+            .map(|symbol| symbol.scope_level)
+            .filter(|outer_scope| *outer_scope < inner_scope);
+        if let Some(outer_scope) = shadowed.filter(|_| self.warn_shadowing) {
+            self.warnings.push(SemanticWarning::Shadowing {
+                name: variable.clone(),
+                outer_scope,
+                inner_scope,
+            });
+        }
+
+        match self
+            .symbol_table
+            .declare_variable_at(variable, var_type, span, mutable)
+        {
+            Ok(()) => self.declared.push(variable.clone()),
+            Err(msg) => self.add_error(msg, SemanticErrorType::DuplicateDeclaration, span),
+        }
+    }
+
+    // `-` needs a number and `!` a boolean, operands of unknown type are reported elsewhere
+    fn visit_unary_op(&mut self, op: &UnaryOperator, operand: &ASTNode) -> Option<Type> {
+        let operand_type = self.visit_node(operand)?;
+        let allowed = match op {
+            UnaryOperator::Negate => matches!(operand_type, Type::Integer | Type::Float),
+            UnaryOperator::Not => operand_type == Type::Boolean,
+        };
+        if !allowed {
+            self.add_error(
+                format!(
+                    "Cannot apply {:?} to a value of type {:?}",
+                    op, operand_type
+                ),
+                SemanticErrorType::TypeMismatch,
+                None,
+            );
+        }
+        match op {
+            UnaryOperator::Negate => Some(operand_type),
+            UnaryOperator::Not => Some(Type::Boolean),
+        }
+    }
+
+    fn visit_identifier(&mut self, name: &String, span: Option<Span>) -> Option<Type> {
+        match self.symbol_table.lookup_variable(name) {
+            Some(symbol) if symbol.initialized => {
+                self.read.insert(name.clone());
             }
-            ASTNode::Identifier(name) => match self.symbol_table.lookup_variable(name) {
-                None => {
-                    self.add_error(
-                        format!("Variable not in scope: {}", name),
-                        SemanticErrorType::UndefinedVariable,
-                    );
+            Some(_) => self.add_error(
+                format!("Variable \"{}\" is read before it is assigned", name),
+                SemanticErrorType::UninitializedVariable,
+                span,
+            ),
+            None => self.add_error(
+                format!("Variable not in scope: {}", name),
+                SemanticErrorType::UndefinedVariable,
+                span,
+            ),
+        }
+        self.variable_type(name)
+    }
+
+    // Input is parsed as an f64, so `read` declares a float or reassigns one
+    fn visit_read(&mut self, variable: &String) {
+        match self.symbol_table.lookup_variable(variable) {
+            None => self.declare(variable, Type::Float, None, true),
+            Some(symbol) if !symbol.mutable => self.add_error(
+                format!("Cannot assign to constant \"{}\"", variable),
+                SemanticErrorType::AssignToConst,
+                None,
+            ),
+            Some(_) => {
+                if let Err(msg) = self.symbol_table.assign_variable(variable, Type::Float) {
+                    self.add_error(msg, SemanticErrorType::TypeMismatch, None);
                 }
-                _ => (),
-            },
-            ASTNode::Number(_) => {}
+            }
+        }
+    }
+
+    // Deleting a constant would let it be declared again with a new value
+    fn visit_delete(&mut self, variable: &str) {
+        if self
+            .symbol_table
+            .lookup_variable(variable)
+            .is_some_and(|symbol| !symbol.mutable)
+        {
+            return self.add_error(
+                format!("Cannot delete constant \"{}\"", variable),
+                SemanticErrorType::AssignToConst,
+                None,
+            );
+        }
+        if let Err(msg) = self.symbol_table.remove_variable(variable) {
+            self.add_error(msg, SemanticErrorType::UndefinedVariable, None);
+        }
+    }
+
+    // Either arm can be the result, so both must have the same type
+    fn visit_ternary(
+        &mut self,
+        condition: &ASTNode,
+        then_value: &ASTNode,
+        else_value: &ASTNode,
+    ) -> Option<Type> {
+        self.visit_condition(condition);
+        let then_type = self.visit_node(then_value);
+        let else_type = self.visit_node(else_value);
+        if then_type != else_type {
+            self.add_error(
+                format!(
+                    "Ternary arms have different types: {:?} and {:?}",
+                    then_type, else_type
+                ),
+                SemanticErrorType::TypeMismatch,
+                None,
+            );
+        }
+        then_type
+    }
+
+    fn visit_condition(&mut self, condition: &ASTNode) {
+        self.visit_node(condition);
+        if evaluate_constant(condition).is_some() {
+            self.warnings
+                .push(SemanticWarning::ConstantCondition(condition.clone()));
+        }
+    }
+
+    fn warn_unused_variables(&mut self) {
+        for name in &self.declared {
+            if !self.read.contains(name) {
+                self.warnings
+                    .push(SemanticWarning::UnusedVariable(name.clone()));
+            }
         }
     }
 
-    fn add_error(&mut self, message: String, error_type: SemanticErrorType) {
+    fn add_error(&mut self, message: String, error_type: SemanticErrorType, span: Option<Span>) {
         self.errors.push(SemanticError {
             message,
             error_type,
+            span,
         });
     }
 
-    fn get_expression_type(&mut self, node: &ASTNode) -> Option<Type> {
-        match node {
-            ASTNode::Number(_) => Some(Type::Integer),
-            ASTNode::Identifier(name) => match self.symbol_table.lookup_variable(name) {
-                Some(x) => Some(x.symbol_type.clone()),
-                _ => None,
-            },
-            ASTNode::BinaryOp {
-                left: _,
-                op: _,
-                right: _,
-            } => Some(Type::Integer),
-            _ => None,
+    fn variable_type(&self, name: &str) -> Option<Type> {
+        self.symbol_table
+            .lookup_variable(name)
+            .map(|symbol| symbol.symbol_type.clone())
+    }
+}
+
+// `7 / 2` is 3.5 and `2 ^ -1` is 0.5, so only a non-negative constant exponent keeps
+// an Integer. Other operands are promoted, mismatched ones are reported separately.
+// A Boolean is stored as 0 or 1, so it counts as an Integer in arithmetic.
+fn binary_result_type(
+    op: &BinaryOperator,
+    left_type: Option<Type>,
+    right_type: Option<Type>,
+    right: &ASTNode,
+) -> Option<Type> {
+    match (op, as_number(left_type), as_number(right_type)) {
+        (
+            BinaryOperator::Equal
+            | BinaryOperator::NotEqual
+            | BinaryOperator::LessThan
+            | BinaryOperator::GreaterThan
+            | BinaryOperator::LessEqual
+            | BinaryOperator::GreaterEqual
+            | BinaryOperator::And
+            | BinaryOperator::Or,
+            _,
+            _,
+        ) => Some(Type::Boolean),
+        (BinaryOperator::Power, Some(Type::Integer), Some(Type::Integer))
+            if evaluate_constant(right).is_some_and(|x| x >= 0.0) =>
+        {
+            Some(Type::Integer)
         }
+        (BinaryOperator::Power, _, _)
+        | (BinaryOperator::Divide, Some(Type::Integer), Some(Type::Integer)) => Some(Type::Float),
+        (_, left_type, right_type) => promote(left_type, right_type),
+    }
+}
+
+// An integer mixed with a float is promoted, otherwise the left side decides
+fn promote(left: Option<Type>, right: Option<Type>) -> Option<Type> {
+    match (left, right) {
+        (Some(Type::Integer), Some(Type::Float)) | (Some(Type::Float), Some(Type::Integer)) => {
+            Some(Type::Float)
+        }
+        (left, _) => left,
+    }
+}
+
+fn as_number(value_type: Option<Type>) -> Option<Type> {
+    match value_type {
+        Some(Type::Boolean) => Some(Type::Integer),
+        value_type => value_type,
     }
 }
 
+fn is_numeric(value_type: &Option<Type>) -> bool {
+    matches!(
+        value_type,
+        Some(Type::Integer | Type::Float | Type::Boolean)
+    )
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -206,7 +870,7 @@ mod tests {
         assert_eq!(table.current_scope, 1);
 
         // Down scope state
-        table.exit_scope();
+        assert!(table.exit_scope().is_ok());
         assert_eq!(table.scopes.len(), 1);
         assert_eq!(table.current_scope, 0);
 
@@ -238,17 +902,79 @@ mod tests {
 
         // Out-of-scope variables are lost
         _ = table.declare_variable(&"var2".to_string(), Type::Function);
-        table.exit_scope();
+        assert!(table.exit_scope().is_ok());
+        table.enter_scope();
+        assert_eq!(table.lookup_variable("var2"), None);
+    }
+
+    #[test]
+    fn test_symbol_table_exit_global_scope() {
+        let mut table = SymbolTable::new();
+        _ = table.declare_variable(&"a".to_string(), Type::Integer);
+
+        assert!(table.exit_scope().is_err());
+        assert_eq!(table.scopes.len(), 1);
+        assert!(table.lookup_variable("a").is_some());
+    }
+
+    #[test]
+    fn test_symbol_table_snapshot_restore() {
+        let mut table = SymbolTable::new();
+        _ = table.declare_variable(&"a".to_string(), Type::Integer);
+        let snapshot = table.snapshot();
+
         table.enter_scope();
-        assert_eq!(table.lookup_variable(&"var2".to_string()), None);
+        _ = table.declare_variable(&"b".to_string(), Type::Float);
+        assert!(table.lookup_variable("b").is_some());
+
+        table.restore(snapshot);
+        assert_eq!(table.current_scope(), 0);
+        assert_eq!(table.scopes.len(), 1);
+        assert!(table.lookup_variable("a").is_some());
+        assert_eq!(table.lookup_variable("b"), None);
+    }
+
+    #[test]
+    fn test_symbol_table_reentered_scopes_start_empty() {
+        let mut table = SymbolTable::new();
+        _ = table.declare_variable(&"a".to_string(), Type::Integer);
+
+        for _ in 0..3 {
+            table.enter_scope();
+            assert!(table.scopes[table.current_scope].is_empty());
+
+            // Shadows the outer `a` only inside this block
+            assert!(table
+                .declare_variable(&"a".to_string(), Type::Float)
+                .is_ok());
+            assert!(table
+                .declare_variable(&"b".to_string(), Type::Float)
+                .is_ok());
+
+            table.enter_scope();
+            assert!(table
+                .declare_variable(&"c".to_string(), Type::Float)
+                .is_ok());
+            assert!(table.exit_scope().is_ok());
+            assert_eq!(table.lookup_variable("c"), None);
+
+            assert!(table.exit_scope().is_ok());
+            assert_eq!(table.scopes.len(), 1);
+            assert_eq!(table.lookup_variable("b"), None);
+            assert_eq!(
+                table.lookup_variable("a").unwrap().symbol_type,
+                Type::Integer
+            );
+        }
     }
 
     #[test]
     fn test_semantic_analyzer_undefined_variable() {
         let mut analyzer = SemanticAnalyzer::new();
-        let result = analyzer.analyze(&ASTNode::Program(vec![ASTNode::Identifier(
-            "some_var".to_string(),
-        )]));
+        let result = analyzer.analyze(&ASTNode::Program(vec![ASTNode::Identifier {
+            name: "some_var".to_string(),
+            span: None,
+        }]));
 
         assert!(result.is_err());
         assert_eq!(
@@ -258,24 +984,75 @@ mod tests {
     }
 
     #[test]
-    fn test_semantic_analyzer_duplicate_declaration() {
+    fn test_semantic_analyzer_clear_errors() {
+        let undefined = ASTNode::Program(vec![ASTNode::Identifier {
+            name: "some_var".to_string(),
+            span: None,
+        }]);
+        let mut analyzer = SemanticAnalyzer::new();
+
+        assert!(analyzer.analyze(&undefined).is_err());
+        assert_eq!(analyzer.errors().len(), 1);
+        assert_eq!(
+            analyzer.errors()[0].error_type,
+            SemanticErrorType::UndefinedVariable
+        );
+
+        analyzer.clear_errors();
+        assert!(analyzer.analyze(&ASTNode::Program(vec![])).is_ok());
+        assert!(analyzer.errors().is_empty());
+
+        // Without clearing, errors build up but each analysis only returns its own
+        assert_eq!(analyzer.analyze(&undefined).unwrap_err().len(), 1);
+        assert_eq!(analyzer.analyze(&undefined).unwrap_err().len(), 1);
+        assert_eq!(analyzer.errors().len(), 2);
+    }
+
+    #[test]
+    fn test_semantic_analyzer_reassignment() {
         let mut analyzer = SemanticAnalyzer::new();
         let ast = ASTNode::Program(vec![
             ASTNode::Assignment {
                 variable: "x".to_string(),
+                declared_type: None,
+                constant: false,
+                declaration: false,
                 value: Box::new(ASTNode::Number(1.)),
+                span: None,
             },
             ASTNode::Assignment {
                 variable: "x".to_string(),
+                declared_type: None,
+                constant: false,
+                declaration: false,
                 value: Box::new(ASTNode::Number(2.)),
+                span: None,
             },
         ]);
 
+        assert!(analyzer.analyze(&ast).is_ok());
+    }
+
+    #[test]
+    fn test_semantic_analyzer_reassignment_type_mismatch() {
+        let mut analyzer = SemanticAnalyzer::new();
+        _ = analyzer
+            .symbol_table
+            .declare_variable(&"f".to_string(), Type::Function);
+        let ast = ASTNode::Program(vec![ASTNode::Assignment {
+            variable: "f".to_string(),
+            declared_type: None,
+            constant: false,
+            declaration: false,
+            value: Box::new(ASTNode::Number(1.)),
+            span: None,
+        }]);
+
         let result = analyzer.analyze(&ast);
         assert!(result.is_err());
         assert_eq!(
             result.err().unwrap()[0].error_type,
-            SemanticErrorType::DuplicateDeclaration
+            SemanticErrorType::TypeMismatch
         );
     }
 
@@ -290,9 +1067,15 @@ mod tests {
             .declare_variable(&"y".to_string(), Type::Function);
 
         let node = ASTNode::BinaryOp {
-            left: Box::new(ASTNode::Identifier("x".to_string())),
+            left: Box::new(ASTNode::Identifier {
+                name: "x".to_string(),
+                span: None,
+            }),
             op: crate::BinaryOperator::Add,
-            right: Box::new(ASTNode::Identifier("y".to_string())),
+            right: Box::new(ASTNode::Identifier {
+                name: "y".to_string(),
+                span: None,
+            }),
         };
 
         let result = analyzer.analyze(&node);
@@ -303,4 +1086,258 @@ mod tests {
             SemanticErrorType::TypeMismatch
         );
     }
+
+    #[test]
+    fn test_semantic_analyzer_constant_if_condition() {
+        let mut analyzer = SemanticAnalyzer::new();
+        let ast = ASTNode::Program(vec![ASTNode::If {
+            condition: Box::new(ASTNode::Number(0.)),
+            then_branch: Box::new(ASTNode::Block(vec![])),
+            else_branch: None,
+        }]);
+
+        assert!(analyzer.analyze(&ast).is_ok());
+        assert_eq!(
+            analyzer.warnings(),
+            &[SemanticWarning::ConstantCondition(ASTNode::Number(0.))]
+        );
+    }
+
+    #[test]
+    fn test_semantic_analyzer_constant_while_condition() {
+        let mut analyzer = SemanticAnalyzer::new();
+        let ast = ASTNode::Program(vec![ASTNode::While {
+            condition: Box::new(ASTNode::Number(1.)),
+            body: Box::new(ASTNode::Block(vec![])),
+        }]);
+
+        assert!(analyzer.analyze(&ast).is_ok());
+        assert_eq!(
+            analyzer.warnings(),
+            &[SemanticWarning::ConstantCondition(ASTNode::Number(1.))]
+        );
+    }
+
+    #[test]
+    fn test_semantic_analyzer_variable_condition_is_not_constant() {
+        let mut analyzer = SemanticAnalyzer::new();
+        _ = analyzer
+            .symbol_table
+            .declare_variable(&"x".to_string(), Type::Integer);
+        let ast = ASTNode::Program(vec![ASTNode::While {
+            condition: Box::new(ASTNode::Identifier {
+                name: "x".to_string(),
+                span: None,
+            }),
+            body: Box::new(ASTNode::Block(vec![])),
+        }]);
+
+        assert!(analyzer.analyze(&ast).is_ok());
+        assert!(analyzer.warnings().is_empty());
+    }
+
+    #[test]
+    fn test_semantic_analyzer_disallowed_operator() {
+        let allowed = HashSet::from([
+            BinaryOperator::Add,
+            BinaryOperator::Subtract,
+            BinaryOperator::Multiply,
+        ]);
+        let mut analyzer = SemanticAnalyzer::with_allowed_operators(allowed);
+        let ast = ASTNode::Program(vec![
+            ASTNode::Assignment {
+                variable: "x".to_string(),
+                declared_type: None,
+                constant: false,
+                declaration: false,
+                value: Box::new(ASTNode::BinaryOp {
+                    left: Box::new(ASTNode::Number(4.)),
+                    op: BinaryOperator::Multiply,
+                    right: Box::new(ASTNode::Number(2.)),
+                }),
+                span: None,
+            },
+            ASTNode::Assignment {
+                variable: "y".to_string(),
+                declared_type: None,
+                constant: false,
+                declaration: false,
+                value: Box::new(ASTNode::BinaryOp {
+                    left: Box::new(ASTNode::Number(4.)),
+                    op: BinaryOperator::Divide,
+                    right: Box::new(ASTNode::Number(2.)),
+                }),
+                span: None,
+            },
+        ]);
+
+        let errors = analyzer.analyze(&ast).err().unwrap();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].error_type, SemanticErrorType::DisallowedOperator);
+    }
+
+    #[test]
+    fn test_semantic_analyzer_division_by_literal_zero() {
+        let mut analyzer = SemanticAnalyzer::new();
+        let ast = ASTNode::BinaryOp {
+            left: Box::new(ASTNode::Number(5.)),
+            op: BinaryOperator::Divide,
+            right: Box::new(ASTNode::Number(0.)),
+        };
+
+        let result = analyzer.analyze(&ast);
+        assert_eq!(
+            result.err().unwrap()[0].error_type,
+            SemanticErrorType::DivisionByZero
+        );
+    }
+
+    #[test]
+    fn test_semantic_analyzer_division_by_folded_zero() {
+        let mut analyzer = SemanticAnalyzer::new();
+        let ast = ASTNode::BinaryOp {
+            left: Box::new(ASTNode::Number(5.)),
+            op: BinaryOperator::Divide,
+            right: Box::new(ASTNode::BinaryOp {
+                left: Box::new(ASTNode::Number(3.)),
+                op: BinaryOperator::Subtract,
+                right: Box::new(ASTNode::Number(3.)),
+            }),
+        };
+
+        let result = analyzer.analyze(&ast);
+        assert_eq!(
+            result.err().unwrap()[0].error_type,
+            SemanticErrorType::DivisionByZero
+        );
+    }
+
+    #[test]
+    fn test_semantic_analyzer_division_by_variable() {
+        let mut analyzer = SemanticAnalyzer::new();
+        _ = analyzer
+            .symbol_table
+            .declare_variable(&"x".to_string(), Type::Integer);
+        let ast = ASTNode::BinaryOp {
+            left: Box::new(ASTNode::Number(5.)),
+            op: BinaryOperator::Divide,
+            right: Box::new(ASTNode::Identifier {
+                name: "x".to_string(),
+                span: None,
+            }),
+        };
+
+        assert!(analyzer.analyze(&ast).is_ok());
+    }
+
+    #[test]
+    fn test_semantic_analyzer_unused_variable() {
+        let mut analyzer = SemanticAnalyzer::new();
+        let ast = ASTNode::Program(vec![ASTNode::Assignment {
+            variable: "x".to_string(),
+            declared_type: None,
+            constant: false,
+            declaration: false,
+            value: Box::new(ASTNode::Number(5.)),
+            span: None,
+        }]);
+
+        assert!(analyzer.analyze(&ast).is_ok());
+        assert_eq!(
+            analyzer.warnings(),
+            &[SemanticWarning::UnusedVariable("x".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_semantic_analyzer_shadowing_warning() {
+        let ast = ASTNode::Program(vec![ASTNode::Assignment {
+            variable: "x".to_string(),
+            declared_type: Some(Type::Float),
+            constant: false,
+            declaration: false,
+            value: Box::new(ASTNode::Float(2.5)),
+            span: None,
+        }]);
+        let shadowing = |analyzer: &SemanticAnalyzer| {
+            analyzer
+                .warnings()
+                .iter()
+                .filter(|x| matches!(x, SemanticWarning::Shadowing { .. }))
+                .count()
+        };
+
+        let mut analyzer = SemanticAnalyzer::new();
+        _ = analyzer
+            .symbol_table
+            .declare_variable(&"x".to_string(), Type::Integer);
+        analyzer.symbol_table.enter_scope();
+
+        assert!(analyzer.analyze(&ast).is_ok());
+        assert_eq!(shadowing(&analyzer), 1);
+        assert!(analyzer.warnings().contains(&SemanticWarning::Shadowing {
+            name: "x".to_string(),
+            outer_scope: 0,
+            inner_scope: 1,
+        }));
+
+        let mut analyzer = SemanticAnalyzer::new();
+        analyzer.set_shadowing_warnings(false);
+        _ = analyzer
+            .symbol_table
+            .declare_variable(&"x".to_string(), Type::Integer);
+        analyzer.symbol_table.enter_scope();
+
+        assert!(analyzer.analyze(&ast).is_ok());
+        assert_eq!(shadowing(&analyzer), 0);
+    }
+
+    #[test]
+    fn test_semantic_analyzer_rejects_keyword_names() {
+        let mut analyzer = SemanticAnalyzer::new();
+        let ast = ASTNode::Program(vec![ASTNode::Assignment {
+            variable: "while".to_string(),
+            declared_type: None,
+            constant: false,
+            declaration: false,
+            value: Box::new(ASTNode::Number(1.)),
+            span: None,
+        }]);
+
+        let errors = analyzer.analyze(&ast).unwrap_err();
+        assert_eq!(errors[0].error_type, SemanticErrorType::ReservedKeyword);
+        assert!(analyzer.symbol_table.lookup_variable("while").is_none());
+    }
+
+    #[test]
+    fn test_semantic_analyzer_read_variable_is_used() {
+        let mut analyzer = SemanticAnalyzer::new();
+        let ast = ASTNode::Program(vec![
+            ASTNode::Assignment {
+                variable: "x".to_string(),
+                declared_type: None,
+                constant: false,
+                declaration: false,
+                value: Box::new(ASTNode::Number(5.)),
+                span: None,
+            },
+            ASTNode::Assignment {
+                variable: "y".to_string(),
+                declared_type: None,
+                constant: false,
+                declaration: false,
+                value: Box::new(ASTNode::Identifier {
+                    name: "x".to_string(),
+                    span: None,
+                }),
+                span: None,
+            },
+        ]);
+
+        assert!(analyzer.analyze(&ast).is_ok());
+        assert_eq!(
+            analyzer.warnings(),
+            &[SemanticWarning::UnusedVariable("y".to_string())]
+        );
+    }
 }
@@ -0,0 +1,73 @@
+use std::collections::HashSet;
+
+use crate::ast::{walk, ASTNode, Visitor};
+
+// Collects the variables a subtree reads and writes
+#[derive(Default)]
+pub(crate) struct VariableUsage {
+    pub(crate) reads: HashSet<String>,
+    pub(crate) writes: HashSet<String>,
+}
+
+impl Visitor for VariableUsage {
+    fn visit_identifier(&mut self, name: &str) {
+        self.reads.insert(name.to_string());
+    }
+
+    fn visit_assignment(&mut self, variable: &str, value: &ASTNode) {
+        self.writes.insert(variable.to_string());
+        walk(self, value);
+    }
+
+    fn visit_read(&mut self, variable: &str) {
+        self.writes.insert(variable.to_string());
+    }
+
+    fn visit_delete(&mut self, variable: &str) {
+        self.writes.insert(variable.to_string());
+    }
+}
+
+pub(crate) fn usage_of(node: &ASTNode) -> VariableUsage {
+    let mut usage = VariableUsage::default();
+    walk(&mut usage, node);
+    usage
+}
+
+// Reduces a program to the statements that can affect the final value of `target`.
+// Statements are scanned backwards, tracking which variables are still needed.
+// Control flow is kept whole whenever it writes a needed variable, since its
+// writes may not happen and its condition decides whether they do.
+pub fn slice_for(program: &ASTNode, target: &str) -> ASTNode {
+    let statements = match program {
+        ASTNode::Program(statements) => statements,
+        other => return other.clone(),
+    };
+
+    let mut needed: HashSet<String> = HashSet::from([target.to_string()]);
+    let mut kept: Vec<ASTNode> = vec![];
+
+    for statement in statements.iter().rev() {
+        match statement {
+            // Assignments, including chained ones, reads and deletes always write every variable they name
+            ASTNode::Assignment { .. } | ASTNode::Read(_) | ASTNode::Delete(_) => {
+                let usage = usage_of(statement);
+                if !usage.writes.is_disjoint(&needed) {
+                    needed.retain(|x| !usage.writes.contains(x));
+                    needed.extend(usage.reads);
+                    kept.push(statement.clone());
+                }
+            }
+            other => {
+                let usage = usage_of(other);
+                if !usage.writes.is_disjoint(&needed) {
+                    needed.extend(usage.reads);
+                    kept.push(other.clone());
+                }
+            }
+        }
+    }
+
+    kept.reverse();
+    ASTNode::Program(kept)
+}
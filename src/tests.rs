@@ -1,6 +1,15 @@
+use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
 use crate::ast::*;
+use crate::cache::ProgramCache;
+use crate::interpreter::{disassemble, CompileOptions, Instruction, Interpreter};
 use crate::lexer::*;
-use crate::parser::Parser;
+use crate::math::{self, MathMode};
+use crate::optimizer::{optimize, optimize_instructions};
+use crate::parser::{ParseError, Parser, DEFAULT_MAX_NESTING_DEPTH};
+use crate::semantic_analyzer::{SemanticAnalyzer, SemanticErrorType, SymbolTable, Type};
+use crate::slicing::slice_for;
+use crate::vm::{ArithmeticPolicy, FormatOptions, VmError, VM};
 
 #[test]
 fn test_token_creation() {
@@ -13,14 +22,15 @@ fn test_token_creation() {
 }
 
 #[test]
+#[allow(clippy::approx_constant)]
 fn test_lexer_tokenization() {
     let input = "x = 42 + 3.14 * (_4f - .4) / g;";
     let mut lexer = Lexer::new(input);
     let mut tokens: Vec<Token> = vec![];
 
-    tokens.push(lexer.next_token());
+    tokens.push(lexer.next_token().unwrap());
     while tokens.last() != Some(&Token::EoF) {
-        tokens.push(lexer.next_token());
+        tokens.push(lexer.next_token().unwrap());
     }
 
     assert_eq!(
@@ -30,12 +40,12 @@ fn test_lexer_tokenization() {
             Token::Assign,
             Token::Number(42.0),
             Token::Plus,
-            Token::Number(3.14),
+            Token::Float(3.14),
             Token::Multiply,
             Token::LParen,
             Token::Identifier("_4f".to_string()),
             Token::Minus,
-            Token::Number(0.4),
+            Token::Float(0.4),
             Token::RParen,
             Token::Divide,
             Token::Identifier("g".to_string()),
@@ -45,6 +55,34 @@ fn test_lexer_tokenization() {
     )
 }
 
+#[test]
+fn test_tokenize() {
+    assert_eq!(
+        tokenize("x = 4.5 / g;"),
+        Ok(vec![
+            Token::Identifier("x".to_string()),
+            Token::Assign,
+            Token::Float(4.5),
+            Token::Divide,
+            Token::Identifier("g".to_string()),
+            Token::Semi,
+            Token::EoF
+        ])
+    );
+    assert_eq!(tokenize(""), Ok(vec![Token::EoF]));
+}
+
+#[test]
+fn test_tokenize_stops_at_first_error() {
+    assert_eq!(
+        tokenize("x = 1 @ 0o9;"),
+        Err(LexError::UnexpectedCharacter {
+            character: '@',
+            span: Span { line: 1, column: 7 }
+        })
+    );
+}
+
 #[test]
 fn test_ast_creation() {
     let input = "x = 10 + 5 * 2;";
@@ -54,6 +92,9 @@ fn test_ast_creation() {
     let program = parser.parse_program().ok().unwrap();
     let ast = ASTNode::Program(vec![ASTNode::Assignment {
         variable: "x".to_string(),
+        declared_type: None,
+        constant: false,
+        declaration: false,
         value: boxed_node(ASTNode::BinaryOp {
             left: boxed_node(ASTNode::Number(10.)),
             op: BinaryOperator::Add,
@@ -63,7 +104,2485 @@ fn test_ast_creation() {
                 right: boxed_node(ASTNode::Number(2.)),
             }),
         }),
+        span: Some(Span { line: 1, column: 1 }),
     }]);
 
     assert_eq!(program, ast)
 }
+
+fn compile(input: &str) -> (Vec<Instruction>, Vec<f64>, SymbolTable) {
+    let lexer = Lexer::new(input);
+    let mut parser = Parser::new(lexer);
+    let mut analyzer = SemanticAnalyzer::new();
+
+    let program = parser.parse_program().ok().unwrap();
+    analyzer.analyze(&program).ok().unwrap();
+
+    let (instructions, constants) = Interpreter::new().generate_instructions(&program);
+    (instructions, constants, analyzer.symbol_table)
+}
+
+fn division_policies() -> HashMap<BinaryOperator, ArithmeticPolicy> {
+    HashMap::from([
+        (BinaryOperator::Divide, ArithmeticPolicy::Ieee),
+        (BinaryOperator::Modulo, ArithmeticPolicy::Error),
+    ])
+}
+
+#[test]
+fn test_vm_ieee_policy_divides_by_zero() {
+    let (instructions, constants, symbol_table) = compile("z = 0; x = 1 / z;");
+    let mut vm = VM::with_policies(instructions, constants, symbol_table, division_policies());
+
+    assert_eq!(vm.execute().unwrap().get("x"), Some(&f64::INFINITY));
+}
+
+#[test]
+fn test_vm_error_policy_rejects_modulo_by_zero() {
+    let (instructions, constants, symbol_table) = compile("z = 0; x = 1 % z;");
+    let mut vm = VM::with_policies(instructions, constants, symbol_table, division_policies());
+
+    let err = vm.execute().unwrap_err();
+    assert_eq!(err, VmError::DivisionByZero(BinaryOperator::Modulo));
+    assert_eq!(err.to_string(), "Cannot take modulo by zero!");
+}
+
+#[test]
+fn test_parse_single_expression() {
+    let mut parser = Parser::new(Lexer::new("3 + 4 * 2"));
+
+    let expr = parser.parse_single_expression().ok().unwrap();
+    let ast = ASTNode::BinaryOp {
+        left: boxed_node(ASTNode::Number(3.)),
+        op: BinaryOperator::Add,
+        right: boxed_node(ASTNode::BinaryOp {
+            left: boxed_node(ASTNode::Number(4.)),
+            op: BinaryOperator::Multiply,
+            right: boxed_node(ASTNode::Number(2.)),
+        }),
+    };
+
+    assert_eq!(expr, ast)
+}
+
+#[test]
+fn test_parser_from_tokens() {
+    let tokens = vec![
+        Token::Identifier("x".to_string()),
+        Token::Assign,
+        Token::Number(5.0),
+        Token::Semi,
+    ];
+    let program = Parser::from_tokens(tokens).parse_program().unwrap();
+
+    assert_eq!(
+        program,
+        ASTNode::Program(vec![ASTNode::Assignment {
+            variable: "x".to_string(),
+            declared_type: None,
+            constant: false,
+            declaration: false,
+            value: Box::new(ASTNode::Number(5.0)),
+            span: Some(Span { line: 1, column: 1 }),
+        }])
+    );
+
+    // Running out of tokens mid-statement is an unexpected EoF
+    let tokens = vec![Token::Identifier("x".to_string()), Token::Assign];
+    assert!(Parser::from_tokens(tokens).parse_program().is_err());
+}
+
+#[test]
+fn test_parse_single_expression_rejects_trailing_tokens() {
+    let mut parser = Parser::new(Lexer::new("3 + 4;"));
+
+    assert!(parser.parse_single_expression().is_err());
+}
+
+#[test]
+fn test_visitor_counts_numbers() {
+    struct NumberCounter {
+        count: usize,
+    }
+
+    impl Visitor for NumberCounter {
+        fn visit_number(&mut self, _value: f64) {
+            self.count += 1;
+        }
+    }
+
+    let mut parser = Parser::new(Lexer::new("x = 10 + 5 * 2; y = x - 3;"));
+    let program = parser.parse_program().ok().unwrap();
+
+    let mut counter = NumberCounter { count: 0 };
+    walk(&mut counter, &program);
+
+    assert_eq!(counter.count, 4);
+}
+
+#[test]
+fn test_optimizer_removes_dead_if_branch() {
+    let input = "if (0) { x = 1; } else { y = 2; } while (0) { z = 3; }";
+    let mut parser = Parser::new(Lexer::new(input));
+    let program = parser.parse_program().ok().unwrap();
+
+    let optimized = optimize(&program);
+    let ast = ASTNode::Program(vec![ASTNode::Block(vec![ASTNode::Assignment {
+        variable: "y".to_string(),
+        declared_type: None,
+        constant: false,
+        declaration: false,
+        value: boxed_node(ASTNode::Number(2.)),
+        span: Some(Span {
+            line: 1,
+            column: 26,
+        }),
+    }])]);
+
+    assert_eq!(optimized, ast)
+}
+
+#[test]
+fn test_optimizer_algebraic_identities() {
+    let simplify = |source: &str| {
+        let expr = Parser::new(Lexer::new(source))
+            .parse_single_expression()
+            .unwrap();
+        strip_spans(&optimize(&expr))
+    };
+    let x = ASTNode::Identifier {
+        name: "x".to_string(),
+        span: None,
+    };
+
+    assert_eq!(simplify("x * 1"), x);
+    assert_eq!(simplify("1 * x"), x);
+    assert_eq!(simplify("x + 0"), x);
+    assert_eq!(simplify("0 + x"), x);
+    assert_eq!(simplify("x - 0"), x);
+    assert_eq!(simplify("x / 1"), x);
+    assert_eq!(simplify("x * 0"), ASTNode::Number(0.));
+    assert_eq!(simplify("x * 1 + 0"), x);
+    assert_eq!(simplify("(x - 0) * (2 - 1)"), x);
+
+    // Not identities, since the operands are in the wrong place
+    assert_eq!(to_rpn(&simplify("0 - x")).unwrap(), "0 x -");
+    assert_eq!(to_rpn(&simplify("1 / x")).unwrap(), "1 x /");
+
+    // A factor with side effects is kept even when multiplied by zero
+    let program = optimize(
+        &Parser::new(Lexer::new("x = 0; y = { x = 5; x } * 0;"))
+            .parse_program()
+            .unwrap(),
+    );
+    let mut analyzer = SemanticAnalyzer::new();
+    analyzer.analyze(&program).unwrap();
+    let (instructions, constants) = Interpreter::new().generate_instructions(&program);
+    let storage = VM::new(instructions, constants, analyzer.symbol_table)
+        .execute()
+        .unwrap();
+    assert_eq!(storage["x"], 5.0);
+    assert_eq!(storage["y"], 0.0);
+}
+
+#[test]
+fn test_optimizer_simplifies_statements() {
+    let mut parser = Parser::new(Lexer::new("y = 2; x = y * 1 + 0; if (y * 0) { x = 0; }"));
+    let program = optimize(&parser.parse_program().unwrap());
+    assert_eq!(
+        strip_spans(&program),
+        strip_spans(
+            &Parser::new(Lexer::new("y = 2; x = y;"))
+                .parse_program()
+                .unwrap()
+        )
+    );
+}
+
+#[test]
+fn test_if_else_execution() {
+    let (instructions, constants, symbol_table) =
+        compile("a = 0; if (a) { x = 1; } else { z = 2; } if (a + 1) { y = 3; }");
+    let mut vm = VM::new(instructions, constants, symbol_table);
+    let storage = vm.execute().unwrap();
+
+    assert_eq!(storage.get("x"), None);
+    assert_eq!(storage.get("z"), Some(&2.0));
+    assert_eq!(storage.get("y"), Some(&3.0));
+}
+
+#[test]
+fn test_number_literal_types() {
+    let (_, _, symbol_table) = compile("x = 5; y = 5.0;");
+
+    assert_eq!(
+        symbol_table.lookup_variable("x").unwrap().symbol_type,
+        Type::Integer
+    );
+    assert_eq!(
+        symbol_table.lookup_variable("y").unwrap().symbol_type,
+        Type::Float
+    );
+}
+
+#[test]
+fn test_mixed_integer_float_promotes_to_float() {
+    let (_, _, symbol_table) = compile("x = 5 + 2.5; y = 7 / 2; z = 7 // 2; w = 3 * 2;");
+    let type_of = |name: &str| {
+        symbol_table
+            .lookup_variable(name)
+            .unwrap()
+            .symbol_type
+            .clone()
+    };
+    assert_eq!(type_of("x"), Type::Float);
+    assert_eq!(type_of("y"), Type::Float);
+    assert_eq!(type_of("z"), Type::Integer);
+    assert_eq!(type_of("w"), Type::Integer);
+
+    // An Integer variable widens to Float, unless it was annotated
+    let (_, _, symbol_table) = compile("x = 5; x = x + 2.5;");
+    assert_eq!(
+        symbol_table.lookup_variable("x").unwrap().symbol_type,
+        Type::Float
+    );
+    assert_eq!(
+        crate::run_checked("x = 8; x /= 4;"),
+        Ok("{x: 2}".to_string())
+    );
+    assert_eq!(
+        crate::run_checked("x = 8; x = x / 4;"),
+        Ok("{x: 2}".to_string())
+    );
+    assert_eq!(
+        crate::run_checked("x = 1; x += 1.5;"),
+        Ok("{x: 2.5}".to_string())
+    );
+
+    let mut parser = Parser::new(Lexer::new("x: int = 5; x = x + 2.5;"));
+    let program = parser.parse_program().ok().unwrap();
+    let errors = SemanticAnalyzer::new().analyze(&program).unwrap_err();
+    assert_eq!(errors[0].error_type, SemanticErrorType::TypeMismatch);
+}
+
+#[test]
+fn test_prelude_constants() {
+    let mut parser = Parser::new(Lexer::new("x = 2 * pi;"));
+    let program = parser.parse_program().ok().unwrap();
+
+    let mut analyzer = SemanticAnalyzer::with_prelude();
+    assert!(analyzer.analyze(&program).is_ok());
+
+    let (instructions, constants) = Interpreter::new().generate_instructions(&program);
+    let mut vm = VM::with_prelude(instructions, constants, analyzer.symbol_table);
+
+    assert_eq!(vm.execute().unwrap().get("x"), Some(&std::f64::consts::TAU));
+
+    let prelude_run = |source: &str| {
+        let program = Parser::new(Lexer::new(source)).parse_program().unwrap();
+        let mut analyzer = SemanticAnalyzer::with_prelude();
+        analyzer.analyze(&program)?;
+        let (instructions, constants) = Interpreter::new().generate_instructions(&program);
+        let mut vm = VM::with_prelude(instructions, constants, analyzer.symbol_table);
+        Ok::<_, Vec<crate::semantic_analyzer::SemanticError>>(vm.execute().unwrap())
+    };
+
+    let storage = prelude_run("x = pi * 2; y = -inf < e; z = tau;").unwrap();
+    assert_eq!(storage["x"], 2.0 * std::f64::consts::PI);
+    assert_eq!(storage["y"], 1.0);
+    assert_eq!(storage["z"], std::f64::consts::TAU);
+
+    for source in ["pi = 3.0;", "inf += 1.0;", "read e;"] {
+        let errors = prelude_run(source).unwrap_err();
+        assert_eq!(
+            errors[0].error_type,
+            SemanticErrorType::AssignToConst,
+            "{}",
+            source
+        );
+    }
+}
+
+#[test]
+fn test_compound_assignment_desugaring() {
+    let pairs = [
+        ("x += 5;", "x = x + (5);"),
+        ("x -= 2;", "x = x - (2);"),
+        ("x *= 3 + 1;", "x = x * (3 + 1);"),
+        ("x /= 4;", "x = x / (4);"),
+    ];
+
+    for (compound, desugared) in pairs {
+        let compound = Parser::new(Lexer::new(compound)).parse_program().unwrap();
+        let desugared = Parser::new(Lexer::new(desugared)).parse_program().unwrap();
+        assert_eq!(strip_spans(&compound), strip_spans(&desugared));
+    }
+}
+
+#[test]
+fn test_compound_assignment_to_undeclared_variable() {
+    let mut parser = Parser::new(Lexer::new("x += 5;"));
+    let program = parser.parse_program().ok().unwrap();
+
+    let mut analyzer = SemanticAnalyzer::new();
+    let result = analyzer.analyze(&program);
+
+    assert_eq!(
+        result.err().unwrap()[0].error_type,
+        SemanticErrorType::UndefinedVariable
+    );
+}
+
+#[test]
+fn test_increment_decrement_desugaring() {
+    for (step, desugared) in [("x++;", "x = x + 1;"), ("x--;", "x = x - 1;")] {
+        let step = Parser::new(Lexer::new(step)).parse_program().unwrap();
+        let desugared = Parser::new(Lexer::new(desugared)).parse_program().unwrap();
+        assert_eq!(strip_spans(&step), strip_spans(&desugared));
+    }
+
+    let (instructions, constants, symbol_table) = compile("x = 5; x++; x++; y = 0; y--;");
+    let storage = VM::new(instructions, constants, symbol_table)
+        .execute()
+        .unwrap();
+    assert_eq!(storage.get("x"), Some(&7.0));
+    assert_eq!(storage.get("y"), Some(&-1.0));
+}
+
+#[test]
+fn test_increment_undeclared_variable() {
+    let mut parser = Parser::new(Lexer::new("x++;"));
+    let program = parser.parse_program().ok().unwrap();
+
+    let errors = SemanticAnalyzer::new().analyze(&program).unwrap_err();
+    assert_eq!(errors[0].error_type, SemanticErrorType::UndefinedVariable);
+}
+
+#[test]
+fn test_reassignment_in_while_loop() {
+    let (instructions, constants, symbol_table) =
+        compile("n = 3; x = 0; while (n) { n -= 1; x += 2; }");
+    let mut vm = VM::new(instructions, constants, symbol_table);
+    let storage = vm.execute().unwrap();
+
+    assert_eq!(storage.get("n"), Some(&0.0));
+    assert_eq!(storage.get("x"), Some(&6.0));
+}
+
+#[test]
+fn test_run_and_keep_continues_with_fragment() {
+    let first = crate::run_and_keep("x = 5;").ok().unwrap();
+
+    let mut parser = Parser::new(Lexer::new("y = x + 1;"));
+    let program = parser.parse_program().ok().unwrap();
+
+    let mut analyzer = SemanticAnalyzer::new();
+    analyzer.symbol_table = first.symbol_table().clone();
+    assert!(analyzer.analyze(&program).is_ok());
+
+    let (instructions, constants) = Interpreter::new().generate_instructions(&program);
+    let mut second = VM::new(instructions, constants, analyzer.symbol_table);
+    let storage = second.execute_with_inputs(first.storage().clone()).unwrap();
+
+    assert_eq!(storage.get("x"), Some(&5.0));
+    assert_eq!(storage.get("y"), Some(&6.0));
+}
+
+#[test]
+fn test_run_and_keep_reports_errors() {
+    assert!(matches!(
+        crate::run_and_keep("x = ;"),
+        Err(crate::RunError::Parse(_))
+    ));
+    assert!(matches!(
+        crate::run_and_keep("x = y;"),
+        Err(crate::RunError::Semantic(_))
+    ));
+}
+
+fn malformed_number_reason(source: &str) -> String {
+    match Lexer::new(source).next_token() {
+        Err(LexError::MalformedNumber { reason, .. }) => reason,
+        other => panic!("Expected a malformed number, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_lexer_reset() {
+    let mut lexer = Lexer::new("alpha = 1;\nbeta = 2;");
+    lexer.next_token().unwrap();
+    lexer.next_token().unwrap();
+
+    lexer.reset("y");
+    assert_eq!(lexer.next_token(), Ok(Token::Identifier("y".to_string())));
+    assert_eq!(lexer.span(), Span { line: 1, column: 1 });
+    assert_eq!(lexer.next_token(), Ok(Token::EoF));
+    assert_eq!(lexer.next_token(), Ok(Token::EoF));
+}
+
+#[test]
+fn test_lexer_radix_literals() {
+    let mut lexer = Lexer::new("0o17 0r3:1201 0r36:z 0");
+
+    assert_eq!(lexer.next_token(), Ok(Token::Number(15.0)));
+    assert_eq!(lexer.next_token(), Ok(Token::Number(46.0)));
+    assert_eq!(lexer.next_token(), Ok(Token::Number(35.0)));
+    assert_eq!(lexer.next_token(), Ok(Token::Number(0.0)));
+    assert_eq!(lexer.next_token(), Ok(Token::EoF));
+}
+
+#[test]
+fn test_lexer_scientific_notation() {
+    let mut lexer = Lexer::new("1e3 2.5e-4 6.02E+23 3 e");
+
+    assert_eq!(lexer.next_token(), Ok(Token::Float(1000.0)));
+    assert_eq!(lexer.next_token(), Ok(Token::Float(2.5e-4)));
+    assert_eq!(lexer.next_token(), Ok(Token::Float(6.02e23)));
+    assert_eq!(lexer.next_token(), Ok(Token::Number(3.0)));
+    assert_eq!(lexer.next_token(), Ok(Token::Identifier("e".to_string())));
+    assert_eq!(lexer.next_token(), Ok(Token::EoF));
+}
+
+#[test]
+fn test_lexer_malformed_exponent() {
+    assert_eq!(
+        malformed_number_reason("1e"),
+        "expected digits after the exponent"
+    );
+}
+
+#[test]
+fn test_lexer_leading_and_trailing_decimal_point() {
+    let mut lexer = Lexer::new(".5 5. 0.25");
+
+    assert_eq!(lexer.next_token(), Ok(Token::Float(0.5)));
+    assert_eq!(lexer.next_token(), Ok(Token::Float(5.0)));
+    assert_eq!(lexer.next_token(), Ok(Token::Float(0.25)));
+    assert_eq!(lexer.next_token(), Ok(Token::EoF));
+
+    for source in [".", "x = . + 1;"] {
+        assert!(
+            matches!(tokenize(source), Err(LexError::MalformedNumber { .. })),
+            "{}",
+            source
+        );
+    }
+    assert_eq!(
+        malformed_number_reason("."),
+        "expected a digit before or after '.'"
+    );
+}
+
+#[test]
+fn test_lexer_number_forms() {
+    let mut lexer = Lexer::new("42 4.2 .5 5. 4.2e-3 1E3 2.e1 1_0e1_0 0xff");
+
+    assert_eq!(lexer.next_token(), Ok(Token::Number(42.0)));
+    assert_eq!(lexer.next_token(), Ok(Token::Float(4.2)));
+    assert_eq!(lexer.next_token(), Ok(Token::Float(0.5)));
+    assert_eq!(lexer.next_token(), Ok(Token::Float(5.0)));
+    assert_eq!(lexer.next_token(), Ok(Token::Float(4.2e-3)));
+    assert_eq!(lexer.next_token(), Ok(Token::Float(1000.0)));
+    assert_eq!(lexer.next_token(), Ok(Token::Float(20.0)));
+    assert_eq!(lexer.next_token(), Ok(Token::Float(10e10)));
+    assert_eq!(lexer.next_token(), Ok(Token::Number(255.0)));
+    assert_eq!(lexer.next_token(), Ok(Token::EoF));
+
+    // None of these may quietly become 0
+    for source in [".", ".e5", "1e", "1e+", "1e-x", "0x", "1_e5"] {
+        assert!(
+            matches!(tokenize(source), Err(LexError::MalformedNumber { .. })),
+            "{}",
+            source
+        );
+    }
+}
+
+#[test]
+fn test_lexer_digit_separators() {
+    let mut lexer = Lexer::new("1_000_000 1.234_567 1_0.2_5");
+
+    assert_eq!(lexer.next_token(), Ok(Token::Number(1000000.0)));
+    assert_eq!(lexer.next_token(), Ok(Token::Float(1.234567)));
+    assert_eq!(lexer.next_token(), Ok(Token::Float(10.25)));
+    assert_eq!(lexer.next_token(), Ok(Token::EoF));
+}
+
+#[test]
+fn test_lexer_misplaced_digit_separators() {
+    for source in ["_5", "5_", "5__0", "5_.0", "5._0"] {
+        assert_eq!(
+            malformed_number_reason(source),
+            "'_' must be between two digits",
+            "{}",
+            source
+        );
+    }
+}
+
+#[test]
+fn test_lexer_hex_and_binary_literals() {
+    let mut lexer = Lexer::new("0xFF 0x1a 0b1010");
+
+    assert_eq!(lexer.next_token(), Ok(Token::Number(255.0)));
+    assert_eq!(lexer.next_token(), Ok(Token::Number(26.0)));
+    assert_eq!(lexer.next_token(), Ok(Token::Number(10.0)));
+    assert_eq!(lexer.next_token(), Ok(Token::EoF));
+}
+
+#[test]
+fn test_lexer_invalid_hex_digit() {
+    assert_eq!(
+        malformed_number_reason("0xG"),
+        "'G' is not a valid base 16 digit"
+    );
+}
+
+#[test]
+fn test_lexer_invalid_binary_digit() {
+    assert_eq!(
+        malformed_number_reason("0b2"),
+        "'2' is not a valid base 2 digit"
+    );
+}
+
+#[test]
+fn test_lexer_invalid_octal_digit() {
+    assert_eq!(
+        malformed_number_reason("0o18"),
+        "'8' is not a valid base 8 digit"
+    );
+}
+
+#[test]
+fn test_lexer_invalid_radix_digit() {
+    assert_eq!(
+        malformed_number_reason("0r3:1231"),
+        "'3' is not a valid base 3 digit"
+    );
+}
+
+#[test]
+fn test_lexer_invalid_radix() {
+    assert_eq!(
+        malformed_number_reason("0r37:1"),
+        "radix must be between 2 and 36"
+    );
+}
+
+#[test]
+fn test_parse_error_missing_semicolon() {
+    let mut parser = Parser::new(Lexer::new("x = 5 y = 6;"));
+
+    assert_eq!(
+        parser.parse_program(),
+        Err(ParseError::UnexpectedToken {
+            expected: "Semi".to_string(),
+            found: Token::Identifier("y".to_string()),
+        })
+    );
+}
+
+#[test]
+fn test_optional_final_semicolon() {
+    assert_eq!(crate::run("x = 5").unwrap()["x"], 5.0);
+    assert_eq!(crate::run("x = 5; y = x * 2").unwrap()["y"], 10.0);
+    assert!(Parser::new(Lexer::new("x = 5; x + 1"))
+        .parse_program()
+        .is_ok());
+
+    let mut parser = Parser::new(Lexer::new("x = 5 y = 6"));
+    assert_eq!(
+        parser.parse_program(),
+        Err(ParseError::UnexpectedToken {
+            expected: "Semi".to_string(),
+            found: Token::Identifier("y".to_string()),
+        })
+    );
+}
+
+#[test]
+fn test_parse_error_reserved_keyword() {
+    let err = Parser::new(Lexer::new("x = 1;\nif = 5;"))
+        .parse_program()
+        .unwrap_err();
+    assert_eq!(
+        err,
+        ParseError::ReservedKeyword {
+            keyword: "if".to_string(),
+            span: Span { line: 2, column: 1 },
+        }
+    );
+    assert_eq!(
+        err.to_string(),
+        "'if' is a reserved keyword and cannot be used as a name (line 2, column 1)"
+    );
+
+    for source in ["while += 1;", "x = else + 1;", "const const = 1;"] {
+        assert!(
+            matches!(
+                Parser::new(Lexer::new(source)).parse_program(),
+                Err(ParseError::ReservedKeyword { .. })
+            ),
+            "{}",
+            source
+        );
+    }
+}
+
+#[test]
+fn test_parse_error_unexpected_eof() {
+    let mut parser = Parser::new(Lexer::new("x = "));
+
+    assert_eq!(parser.parse_program(), Err(ParseError::UnexpectedEof));
+}
+
+#[test]
+fn test_parse_error_unclosed_paren() {
+    let mut parser = Parser::new(Lexer::new("x = 1;\ny = (3 + 4;"));
+    let err = parser.parse_program().unwrap_err();
+
+    assert_eq!(err, ParseError::UnclosedParen(Span { line: 2, column: 5 }));
+    assert_eq!(err.to_string(), "Unmatched '(' opened at line 2, column 5");
+}
+
+#[test]
+fn test_parse_error_empty_parens() {
+    let mut parser = Parser::new(Lexer::new("x = ();"));
+    let err = parser.parse_program().unwrap_err();
+
+    assert_eq!(err, ParseError::EmptyParens(Span { line: 1, column: 5 }));
+    assert_eq!(
+        err.to_string(),
+        "Parentheses at line 1, column 5 contain no expression"
+    );
+}
+
+#[test]
+fn test_parse_error_unclosed_brace() {
+    let parse = |source: &str| Parser::new(Lexer::new(source)).parse_program().unwrap_err();
+
+    let err = parse("{ x = 1;");
+    assert_eq!(err, ParseError::UnclosedBrace(Span { line: 1, column: 1 }));
+    assert_eq!(
+        err.to_string(),
+        "Unmatched '{' opened at line 1, column 1, the input ended before its '}'"
+    );
+
+    // The innermost brace that is still open is reported
+    assert_eq!(
+        parse("x = 1;\nwhile (x) {\n  if (x) { x = 0; }\n  y = 2"),
+        ParseError::UnclosedBrace(Span {
+            line: 2,
+            column: 11
+        })
+    );
+    assert_eq!(
+        parse("x = { y = 1; y"),
+        ParseError::UnclosedBrace(Span { line: 1, column: 5 })
+    );
+    assert_eq!(
+        parse("x = { y = 1;"),
+        ParseError::UnclosedBrace(Span { line: 1, column: 5 })
+    );
+}
+
+#[test]
+fn test_parse_error_stray_rparen() {
+    let mut parser = Parser::new(Lexer::new("x = 3 + 4);"));
+    let err = parser.parse_program().unwrap_err();
+
+    assert_eq!(
+        err,
+        ParseError::UnexpectedRParen(Span {
+            line: 1,
+            column: 10
+        })
+    );
+    assert_eq!(err.to_string(), "Unmatched ')' at line 1, column 10");
+}
+
+#[test]
+fn test_parse_error_nesting_too_deep() {
+    let source = format!("x = {}1{};", "(".repeat(100_000), ")".repeat(100_000));
+    let mut parser = Parser::new(Lexer::new(&source));
+    assert_eq!(
+        parser.parse_program(),
+        Err(ParseError::NestingTooDeep(DEFAULT_MAX_NESTING_DEPTH))
+    );
+
+    let source = format!("x = {}1{};", "(".repeat(20), ")".repeat(20));
+    let mut parser = Parser::new(Lexer::new(&source));
+    parser.set_max_depth(10);
+    assert_eq!(parser.parse_program(), Err(ParseError::NestingTooDeep(10)));
+
+    let source = format!("{}x = 1;{}", "{".repeat(1000), "}".repeat(1000));
+    let mut parser = Parser::new(Lexer::new(&source));
+    assert!(matches!(
+        parser.parse_program(),
+        Err(ParseError::NestingTooDeep(_))
+    ));
+
+    let source = format!("x = {}1{};", "(".repeat(200), ")".repeat(200));
+    assert_eq!(crate::run(&source).unwrap()["x"], 1.0);
+}
+
+#[test]
+fn test_instruction_provenance() {
+    let mut parser = Parser::new(Lexer::new("x = 1; y = 5 * 2;"));
+    let program = parser.parse_program().ok().unwrap();
+
+    let (instructions, provenance) = Interpreter::new().generate_with_provenance(&program);
+    assert_eq!(instructions.len(), provenance.len());
+
+    let multiply = instructions
+        .iter()
+        .position(|x| matches!(x, Instruction::Multiply))
+        .unwrap();
+    assert_eq!(provenance[multiply], vec![1, 0]);
+    assert_eq!(
+        node_at(&program, &provenance[multiply]),
+        Some(&ASTNode::BinaryOp {
+            left: boxed_node(ASTNode::Number(5.)),
+            op: BinaryOperator::Multiply,
+            right: boxed_node(ASTNode::Number(2.)),
+        })
+    );
+}
+
+#[test]
+fn test_instruction_line_table() {
+    let source = "x = 5;\ny = x + 10;\nw = 0;\nz = y / w;";
+    let mut parser = Parser::new(Lexer::new(source));
+    let program = parser.parse_program().ok().unwrap();
+    let mut analyzer = SemanticAnalyzer::new();
+    analyzer.analyze(&program).unwrap();
+
+    let mut interpreter = Interpreter::new();
+    let (instructions, line_table) = interpreter.generate_with_line_table(&program);
+    assert_eq!(instructions.len(), line_table.len());
+
+    let store_y = instructions
+        .iter()
+        .position(|x| matches!(x, Instruction::StoreVariable(name) if name == "y"))
+        .unwrap();
+    assert!(line_table[2..=store_y]
+        .iter()
+        .all(|x| x.map(|x| x.line) == Some(2)));
+    assert_eq!(line_table[2], Some(Span { line: 2, column: 5 }));
+    assert_eq!(line_table.last(), Some(&None));
+
+    let constants = interpreter.constants().to_vec();
+    let mut vm = VM::new(instructions, constants, analyzer.symbol_table);
+    vm.set_line_table(line_table);
+    assert!(vm.execute().is_err());
+    assert_eq!(vm.current_span().map(|x| x.line), Some(4));
+}
+
+#[test]
+fn test_lexer_token_spans() {
+    let mut lexer = Lexer::new("x = 1;\n  yy = x;");
+    let mut spans: Vec<Span> = vec![];
+    while lexer.next_token() != Ok(Token::EoF) {
+        spans.push(lexer.span());
+    }
+
+    assert_eq!(spans[0], Span { line: 1, column: 1 });
+    assert_eq!(spans[3], Span { line: 1, column: 6 });
+    assert_eq!(spans[4], Span { line: 2, column: 3 });
+    assert_eq!(spans[6], Span { line: 2, column: 8 });
+}
+
+#[test]
+fn test_undefined_variable_error_span() {
+    let mut parser = Parser::new(Lexer::new("x = 1;\ny = x +\n    some_var;"));
+    let program = parser.parse_program().ok().unwrap();
+
+    let errors = SemanticAnalyzer::new().analyze(&program).err().unwrap();
+    assert_eq!(errors[0].error_type, SemanticErrorType::UndefinedVariable);
+    assert_eq!(errors[0].span, Some(Span { line: 3, column: 5 }));
+}
+
+#[test]
+fn test_vm_watch_history() {
+    let (instructions, constants, symbol_table) =
+        compile("n = 3; x = 0; while (n) { x += n; n -= 1; } y = x;");
+    let mut vm = VM::new(instructions, constants, symbol_table);
+    vm.watch(&["x", "z"]);
+    vm.execute().unwrap();
+
+    let history = vm.watch_history();
+    assert_eq!(history.get("x"), Some(&vec![0.0, 3.0, 5.0, 6.0]));
+    assert_eq!(history.get("z"), Some(&vec![]));
+    assert_eq!(history.get("n"), None);
+
+    // History restarts with each execution
+    vm.execute().unwrap();
+    assert_eq!(vm.watch_history()["x"].len(), 4);
+}
+
+#[test]
+fn test_comma_separated_assignments() {
+    let comma = Parser::new(Lexer::new("x = 1, y = 2, z = x + y;"))
+        .parse_program()
+        .unwrap();
+    let separate = Parser::new(Lexer::new("x = 1; y = 2; z = x + y;"))
+        .parse_program()
+        .unwrap();
+
+    assert_eq!(strip_spans(&comma), strip_spans(&separate));
+
+    let (instructions, constants, symbol_table) = compile("x = 1, y = 2, z = x + y;");
+    assert_eq!(
+        VM::new(instructions, constants, symbol_table)
+            .execute()
+            .unwrap()
+            .get("z"),
+        Some(&3.0)
+    );
+}
+
+#[test]
+fn test_slice_for_drops_unrelated_statements() {
+    let program = Parser::new(Lexer::new("a = 1; b = 2; c = a + 1;"))
+        .parse_program()
+        .unwrap();
+    let expected = Parser::new(Lexer::new("a = 1; c = a + 1;"))
+        .parse_program()
+        .unwrap();
+
+    assert_eq!(
+        strip_spans(&slice_for(&program, "c")),
+        strip_spans(&expected)
+    );
+}
+
+#[test]
+fn test_slice_for_follows_overwrites_and_loops() {
+    let input = "a = 1; n = 3; b = 0; a = 2; while (n) { b += a; n -= 1; } c = 5; d = b;";
+    let program = Parser::new(Lexer::new(input)).parse_program().unwrap();
+    let expected = Parser::new(Lexer::new(
+        "n = 3; b = 0; a = 2; while (n) { b += a; n -= 1; } d = b;",
+    ))
+    .parse_program()
+    .unwrap();
+
+    assert_eq!(
+        strip_spans(&slice_for(&program, "d")),
+        strip_spans(&expected)
+    );
+}
+
+#[test]
+fn test_vm_reset_and_load() {
+    let (instructions, constants, symbol_table) = compile("x = 2; y = x * 3;");
+    let mut vm = VM::new(instructions, constants, symbol_table);
+    let first = vm.execute().unwrap();
+    assert_eq!(vm.execute().unwrap(), first);
+
+    vm.reset();
+    assert!(vm.storage().is_empty());
+
+    let (instructions, constants, symbol_table) = compile("z = 5;");
+    vm.load(instructions, constants, symbol_table);
+    assert_eq!(
+        vm.execute().unwrap(),
+        HashMap::from([("z".to_string(), 5.0)])
+    );
+}
+
+#[test]
+fn test_vm_strict_math() {
+    let (instructions, constants, symbol_table) = compile("a = 1e308; x = a * 10.0;");
+    let mut vm = VM::new(instructions, constants, symbol_table);
+    assert_eq!(vm.execute().unwrap()["x"], f64::INFINITY);
+
+    vm.set_strict_math(true);
+    assert_eq!(vm.execute(), Err(VmError::NonFiniteResult(f64::INFINITY)));
+    assert_eq!(vm.storage().get("x"), None);
+}
+
+#[test]
+fn test_vm_variable_accessors() {
+    let (instructions, constants, symbol_table) = compile("x = 2; y = x * 3;");
+    let mut vm = VM::new(instructions, constants, symbol_table);
+    vm.execute().unwrap();
+
+    assert_eq!(vm.get("y"), Some(6.0));
+    assert_eq!(vm.get("z"), None);
+
+    let mut variables: Vec<(&String, &f64)> = vm.variables().collect();
+    variables.sort_by(|a, b| a.0.cmp(b.0));
+    assert_eq!(
+        variables,
+        vec![(&"x".to_string(), &2.0), (&"y".to_string(), &6.0)]
+    );
+}
+
+#[test]
+fn test_vm_trace_hook() {
+    let (instructions, constants, symbol_table) = compile("x = 2; y = x * 3;");
+    let mut vm = VM::new(instructions.clone(), constants, symbol_table);
+
+    let trace = Rc::new(RefCell::new(vec![]));
+    let recorded = Rc::clone(&trace);
+    vm.set_trace(Box::new(move |pc, instruction, stack| {
+        recorded
+            .borrow_mut()
+            .push((pc, instruction.to_string(), stack.to_vec()));
+    }));
+    vm.execute().unwrap();
+
+    let trace = trace.borrow();
+    assert_eq!(trace.len(), instructions.len());
+    for (index, (pc, instruction, _)) in trace.iter().enumerate() {
+        assert_eq!(*pc, index);
+        assert_eq!(*instruction, instructions[index].to_string());
+    }
+    // MUL sees `x` and `3` on the stack
+    assert_eq!(trace[4], (4, "MUL".to_string(), vec![2.0, 3.0]));
+}
+
+#[test]
+fn test_execute_with_seeded_inputs() {
+    let mut parser = Parser::new(Lexer::new("y = x * 2;"));
+    let program = parser.parse_program().unwrap();
+
+    let mut analyzer = SemanticAnalyzer::with_inputs(&[("x", Type::Integer)]);
+    assert!(analyzer.analyze(&program).is_ok());
+    assert!(SemanticAnalyzer::new().analyze(&program).is_err());
+
+    let (instructions, constants) = Interpreter::new().generate_instructions(&program);
+    let mut vm = VM::new(instructions, constants, analyzer.symbol_table);
+    for x in [10.0, -3.0, 0.5] {
+        let storage = vm
+            .execute_with_inputs(HashMap::from([("x".to_string(), x)]))
+            .unwrap();
+        assert_eq!(storage["y"], x * 2.0);
+    }
+}
+
+#[test]
+fn test_vm_format_storage() {
+    let (instructions, constants, symbol_table) = compile("y = 7.5; x = 4.0 * 2.5; z = 0 - 3;");
+    let mut vm = VM::new(instructions, constants, symbol_table);
+    vm.execute().unwrap();
+
+    assert_eq!(vm.format_storage(), "{x: 10, y: 7.5, z: -3}");
+}
+
+#[test]
+fn test_negative_literal_folds_into_constant() {
+    let (instructions, constants, _) = compile("x = -5;");
+
+    assert!(matches!(instructions[0], Instruction::LoadConstant(0)));
+    assert_eq!(constants, vec![-5.0]);
+    assert!(!instructions
+        .iter()
+        .any(|x| matches!(x, Instruction::Negate)));
+}
+
+#[test]
+fn test_constant_pool_deduplicates() {
+    let (instructions, constants, symbol_table) = compile("x = 5; y = x + 5; z = 2.5 * 2.5;");
+    assert_eq!(constants, vec![5.0, 2.5]);
+    assert_eq!(
+        instructions
+            .iter()
+            .filter(|x| matches!(x, Instruction::LoadConstant(_)))
+            .count(),
+        4
+    );
+
+    let storage = VM::new(instructions, constants, symbol_table)
+        .execute()
+        .unwrap();
+    assert_eq!(storage["y"], 10.0);
+    assert_eq!(storage["z"], 6.25);
+}
+
+#[test]
+fn test_negate_variable() {
+    let (instructions, constants, symbol_table) = compile("x = 3; y = -x;");
+    assert!(instructions
+        .iter()
+        .any(|x| matches!(x, Instruction::Negate)));
+
+    let mut vm = VM::new(instructions, constants, symbol_table);
+    assert_eq!(vm.execute().unwrap().get("y"), Some(&-3.0));
+}
+
+#[test]
+fn test_instruction_display() {
+    let cases = [
+        (Instruction::LoadConstant(0), "LOAD_CONST #0"),
+        (Instruction::LoadConstant(12), "LOAD_CONST #12"),
+        (Instruction::LoadVariable("x".to_string()), "LOAD x"),
+        (Instruction::StoreVariable("x".to_string()), "STORE x"),
+        (Instruction::Add, "ADD"),
+        (Instruction::Subtract, "SUB"),
+        (Instruction::Divide, "DIV"),
+        (Instruction::Multiply, "MUL"),
+        (Instruction::Modulo, "MOD"),
+        (Instruction::Negate, "NEG"),
+        (Instruction::Dup, "DUP"),
+        (Instruction::Pop, "POP"),
+        (Instruction::Sqrt, "SQRT"),
+        (Instruction::Abs, "ABS"),
+        (Instruction::PowBuiltin, "POW"),
+        (Instruction::Min, "MIN"),
+        (Instruction::Max, "MAX"),
+        (Instruction::Equal, "EQ"),
+        (Instruction::NotEqual, "NE"),
+        (Instruction::LessThan, "LT"),
+        (Instruction::GreaterThan, "GT"),
+        (Instruction::LessEqual, "LE"),
+        (Instruction::GreaterEqual, "GE"),
+        (Instruction::Jump(3), "JUMP 3"),
+        (Instruction::JumpIfFalse(7), "JUMP_IF_FALSE 7"),
+        (Instruction::Call(2), "CALL 2"),
+        (Instruction::Return, "RET"),
+        (Instruction::Stop, "STOP"),
+    ];
+
+    for (instruction, expected) in cases {
+        assert_eq!(instruction.to_string(), expected);
+    }
+}
+
+#[test]
+fn test_vm_call_and_return() {
+    // The subroutine at 3 stores a local, then returns its double
+    let instructions = vec![
+        Instruction::Call(3),
+        Instruction::StoreVariable("x".to_string()),
+        Instruction::Stop,
+        Instruction::LoadConstant(0),
+        Instruction::StoreVariable("half".to_string()),
+        Instruction::LoadVariable("half".to_string()),
+        Instruction::LoadConstant(1),
+        Instruction::Multiply,
+        Instruction::Return,
+    ];
+    let mut symbol_table = SymbolTable::new();
+    _ = symbol_table.declare_variable(&"x".to_string(), Type::Integer);
+
+    let mut vm = VM::new(instructions, vec![21.0, 2.0], symbol_table);
+    let storage = vm.execute().unwrap();
+
+    assert_eq!(storage.get("x"), Some(&42.0));
+    assert_eq!(storage.get("half"), None);
+}
+
+#[test]
+fn test_vm_call_stack_overflow() {
+    // Recurses forever
+    let instructions = vec![Instruction::Call(0)];
+    let mut vm = VM::new(instructions, vec![], SymbolTable::new());
+    vm.set_max_call_depth(8);
+
+    assert_eq!(vm.execute(), Err(VmError::CallStackOverflow(8)));
+}
+
+#[test]
+fn test_vm_stack_overflow() {
+    // Pushes forever
+    let instructions = vec![Instruction::LoadConstant(0), Instruction::Jump(0)];
+    let mut vm = VM::new(instructions, vec![1.0], SymbolTable::new());
+    assert_eq!(
+        vm.execute(),
+        Err(VmError::StackOverflow(crate::vm::DEFAULT_MAX_STACK_DEPTH))
+    );
+
+    let instructions = vec![
+        Instruction::LoadConstant(0),
+        Instruction::Dup,
+        Instruction::Dup,
+        Instruction::Stop,
+    ];
+    let mut vm = VM::new(instructions.clone(), vec![1.0], SymbolTable::new());
+    vm.set_max_stack_depth(2);
+    assert_eq!(vm.execute(), Err(VmError::StackOverflow(2)));
+
+    let mut vm = VM::new(instructions, vec![1.0], SymbolTable::new());
+    vm.set_max_stack_depth(3);
+    assert!(vm.execute().is_ok());
+}
+
+#[test]
+fn test_vm_return_without_call() {
+    let instructions = vec![Instruction::LoadConstant(0), Instruction::Return];
+    let mut vm = VM::new(instructions, vec![1.0], SymbolTable::new());
+
+    assert_eq!(vm.execute(), Err(VmError::ReturnWithoutCall));
+}
+
+#[test]
+fn test_type_annotation_matches() {
+    let (instructions, constants, symbol_table) = compile("x: int = 5; y: float = 2.5;");
+    assert_eq!(
+        symbol_table.lookup_variable("x").unwrap().symbol_type,
+        Type::Integer
+    );
+    assert_eq!(
+        symbol_table.lookup_variable("y").unwrap().symbol_type,
+        Type::Float
+    );
+
+    let mut vm = VM::new(instructions, constants, symbol_table);
+    assert_eq!(vm.execute().unwrap().get("x"), Some(&5.0));
+}
+
+#[test]
+fn test_type_annotation_mismatch() {
+    let mut parser = Parser::new(Lexer::new("x: bool = 5;"));
+    let program = parser.parse_program().ok().unwrap();
+
+    let errors = SemanticAnalyzer::new().analyze(&program).unwrap_err();
+    assert_eq!(errors[0].error_type, SemanticErrorType::TypeMismatch);
+}
+
+#[test]
+fn test_type_annotation_redeclaration() {
+    let mut parser = Parser::new(Lexer::new("x = 5; x: int = 6;"));
+    let program = parser.parse_program().ok().unwrap();
+
+    let errors = SemanticAnalyzer::new().analyze(&program).unwrap_err();
+    assert_eq!(
+        errors[0].error_type,
+        SemanticErrorType::DuplicateDeclaration
+    );
+}
+
+#[test]
+fn test_duplicate_declaration_reports_first_declaration() {
+    let mut parser = Parser::new(Lexer::new("x: int = 5;\ny = x;\n  x: int = 6;"));
+    let program = parser.parse_program().ok().unwrap();
+
+    let errors = SemanticAnalyzer::new().analyze(&program).unwrap_err();
+    assert_eq!(errors[0].span, Some(Span { line: 3, column: 3 }));
+    assert_eq!(
+        errors[0].message,
+        "Trying to declare a duplicate variable \"x\" at line 3, column 3, \
+         first declared at line 1, column 1"
+    );
+}
+
+#[test]
+fn test_logical_operators() {
+    let (instructions, constants, symbol_table) =
+        compile("a = 1 && 0; b = 0 || 2; c = 2 && 3; d = 0 || 0; e = 1 || 0 && 0;");
+    let mut vm = VM::new(instructions, constants, symbol_table);
+    let storage = vm.execute().unwrap();
+
+    assert_eq!(storage.get("a"), Some(&0.0));
+    assert_eq!(storage.get("b"), Some(&1.0));
+    assert_eq!(storage.get("c"), Some(&1.0));
+    assert_eq!(storage.get("d"), Some(&0.0));
+    assert_eq!(storage.get("e"), Some(&1.0));
+
+    // The VM only ever produces 0 or 1, so the result is a Boolean whatever the operands
+    let (_, _, symbol_table) = compile("x = 1 && 0; y = 2.5 || 0.0; z = !x;");
+    for name in ["x", "y", "z"] {
+        assert_eq!(
+            symbol_table.lookup_variable(name).unwrap().symbol_type,
+            Type::Boolean
+        );
+    }
+
+    // ...which still counts as an Integer in arithmetic
+    let storage = crate::run("x = 1 && 2.5; y = x + 1; z = (1 < 2) + 3;").unwrap();
+    assert_eq!(storage["y"], 2.0);
+    assert_eq!(storage["z"], 4.0);
+    let (_, _, symbol_table) = compile("x = (1 < 2) + 3; y = (1 < 2) * 2.5;");
+    assert_eq!(
+        symbol_table.lookup_variable("x").unwrap().symbol_type,
+        Type::Integer
+    );
+    assert_eq!(
+        symbol_table.lookup_variable("y").unwrap().symbol_type,
+        Type::Float
+    );
+}
+
+#[test]
+fn test_logical_operators_short_circuit() {
+    // Dividing by z would halt the VM, so the right sides must be skipped
+    let (instructions, constants, symbol_table) = compile("z = 0; x = 0 && 1 / z; y = 1 || 1 / z;");
+    let mut vm = VM::new(instructions, constants, symbol_table);
+    let storage = vm.execute().unwrap();
+
+    assert_eq!(storage.get("x"), Some(&0.0));
+    assert_eq!(storage.get("y"), Some(&1.0));
+}
+
+#[test]
+fn test_vm_comparison_instructions() {
+    let compare = |left: f64, right: f64, instruction: Instruction| {
+        let instructions = vec![
+            Instruction::LoadConstant(0),
+            Instruction::LoadConstant(1),
+            instruction,
+            Instruction::StoreVariable("result".to_string()),
+        ];
+        let mut vm = VM::new(instructions, vec![left, right], SymbolTable::new());
+        vm.execute().unwrap()["result"]
+    };
+
+    assert_eq!(compare(3.0, 5.0, Instruction::LessThan), 1.0);
+    assert_eq!(compare(5.0, 3.0, Instruction::LessThan), 0.0);
+    assert_eq!(compare(5.0, 3.0, Instruction::GreaterThan), 1.0);
+    assert_eq!(compare(3.0, 3.0, Instruction::LessEqual), 1.0);
+    assert_eq!(compare(2.0, 3.0, Instruction::GreaterEqual), 0.0);
+    assert_eq!(compare(3.0, 3.0, Instruction::Equal), 1.0);
+    assert_eq!(compare(3.0, 3.0, Instruction::NotEqual), 0.0);
+}
+
+#[test]
+fn test_vm_comparisons_with_nan() {
+    let compare = |left: f64, right: f64, instruction: Instruction| {
+        let instructions = vec![
+            Instruction::LoadConstant(0),
+            Instruction::LoadConstant(1),
+            instruction,
+            Instruction::StoreVariable("result".to_string()),
+        ];
+        let mut vm = VM::new(instructions, vec![left, right], SymbolTable::new());
+        vm.execute().unwrap()["result"]
+    };
+
+    for (left, right) in [(f64::NAN, f64::NAN), (f64::NAN, 1.0), (1.0, f64::NAN)] {
+        assert_eq!(compare(left, right, Instruction::Equal), 0.0);
+        assert_eq!(compare(left, right, Instruction::NotEqual), 1.0);
+        assert_eq!(compare(left, right, Instruction::LessThan), 0.0);
+        assert_eq!(compare(left, right, Instruction::GreaterThan), 0.0);
+        assert_eq!(compare(left, right, Instruction::LessEqual), 0.0);
+        assert_eq!(compare(left, right, Instruction::GreaterEqual), 0.0);
+    }
+
+    // `0 / 0` is rejected, but the square root of a negative number is NaN
+    let storage =
+        crate::run("n = sqrt(0 - 1); a = n == n; b = n != n; c = n < 1.0; d = n >= n;").unwrap();
+    assert!(storage["n"].is_nan());
+    assert_eq!(storage["a"], 0.0);
+    assert_eq!(storage["b"], 1.0);
+    assert_eq!(storage["c"], 0.0);
+    assert_eq!(storage["d"], 0.0);
+}
+
+#[test]
+fn test_comparison_expressions() {
+    let (instructions, constants, symbol_table) =
+        compile("a = 3 < 5; b = 5 < 3; c = 1 + 1 == 2; d = 2 != 2 || 4 >= 4;");
+    assert_eq!(
+        symbol_table.lookup_variable("a").unwrap().symbol_type,
+        Type::Boolean
+    );
+
+    let mut vm = VM::new(instructions, constants, symbol_table);
+    let storage = vm.execute().unwrap();
+
+    assert_eq!(storage.get("a"), Some(&1.0));
+    assert_eq!(storage.get("b"), Some(&0.0));
+    assert_eq!(storage.get("c"), Some(&1.0));
+    assert_eq!(storage.get("d"), Some(&1.0));
+}
+
+#[test]
+fn test_empty_program() {
+    for source in ["", "  \n\t ", "# only a comment", "\n# one\n   # two\n"] {
+        let (instructions, constants, symbol_table) = compile(source);
+        let mut vm = VM::new(instructions, constants, symbol_table);
+
+        assert_eq!(vm.execute(), Ok(HashMap::new()));
+    }
+}
+
+#[test]
+fn test_comments_are_skipped() {
+    let (instructions, constants, symbol_table) = compile("x = 1; # x = 2;\ny = x + 1; # trailing");
+    let mut vm = VM::new(instructions, constants, symbol_table);
+
+    assert_eq!(vm.format_storage(), "{}");
+    vm.execute().unwrap();
+    assert_eq!(vm.format_storage(), "{x: 1, y: 2}");
+}
+
+#[test]
+fn test_builtin_functions() {
+    let (instructions, constants, symbol_table) =
+        compile("x = sqrt(16); y = abs(-3); z = pow(2, 10);");
+    let mut vm = VM::new(instructions, constants, symbol_table);
+    let storage = vm.execute().unwrap();
+
+    assert_eq!(storage.get("x"), Some(&4.0));
+    assert_eq!(storage.get("y"), Some(&3.0));
+    assert_eq!(storage.get("z"), Some(&1024.0));
+
+    // The result type comes from every argument, and pow is always a Float
+    let (_, _, symbol_table) = compile("a = max(3, 7.5); b = min(3, 7); c = pow(2, 2);");
+    let type_of = |name: &str| {
+        symbol_table
+            .lookup_variable(name)
+            .unwrap()
+            .symbol_type
+            .clone()
+    };
+    assert_eq!(type_of("a"), Type::Float);
+    assert_eq!(type_of("b"), Type::Integer);
+    assert_eq!(type_of("c"), Type::Float);
+    for source in ["x: int = max(3, 7.5);", "x: int = pow(2, 0.5);"] {
+        let program = Parser::new(Lexer::new(source))
+            .parse_program()
+            .ok()
+            .unwrap();
+        let errors = SemanticAnalyzer::new().analyze(&program).unwrap_err();
+        assert_eq!(errors[0].error_type, SemanticErrorType::TypeMismatch);
+    }
+}
+
+#[test]
+fn test_min_max_builtins() {
+    let run = |source: &str| crate::run(source).unwrap()["x"];
+
+    assert_eq!(run("x = max(3, 7);"), 7.0);
+    assert_eq!(run("x = min(3, 7);"), 3.0);
+    assert_eq!(run("x = max(7, 3);"), 7.0);
+    assert_eq!(run("x = min(4, 4);"), 4.0);
+    assert_eq!(run("x = max(min(1, 2), 0 - 5) + 1;"), 2.0);
+
+    let mut parser = Parser::new(Lexer::new("x = max(1);"));
+    let program = parser.parse_program().ok().unwrap();
+    let errors = SemanticAnalyzer::new().analyze(&program).unwrap_err();
+    assert_eq!(errors[0].error_type, SemanticErrorType::ArityMismatch);
+}
+
+#[test]
+fn test_vm_min_max_with_nan() {
+    let instructions = vec![
+        Instruction::LoadConstant(0),
+        Instruction::LoadConstant(1),
+        Instruction::Max,
+        Instruction::StoreVariable("x".to_string()),
+    ];
+    let mut vm = VM::new(instructions, vec![f64::NAN, 2.0], SymbolTable::new());
+    assert_eq!(vm.execute().unwrap()["x"], 2.0);
+}
+
+#[test]
+fn test_builtin_arity_mismatch() {
+    let mut parser = Parser::new(Lexer::new("x = sqrt(1, 2);"));
+    let program = parser.parse_program().ok().unwrap();
+
+    let errors = SemanticAnalyzer::new().analyze(&program).unwrap_err();
+    assert_eq!(errors[0].error_type, SemanticErrorType::ArityMismatch);
+}
+
+#[test]
+fn test_run() {
+    assert_eq!(
+        crate::run("x = 2 + 3;").unwrap(),
+        HashMap::from([("x".to_string(), 5.0)])
+    );
+}
+
+#[test]
+fn test_run_reports_each_stage() {
+    assert!(matches!(
+        crate::run("x = ;"),
+        Err(crate::RunError::Parse(_))
+    ));
+    assert!(matches!(
+        crate::run("x = y;"),
+        Err(crate::RunError::Semantic(_))
+    ));
+    assert!(matches!(
+        crate::run("z = 0; x = 1 / z;"),
+        Err(crate::RunError::Vm(_))
+    ));
+}
+
+#[test]
+fn test_chained_assignment() {
+    let (instructions, constants, symbol_table) = compile("a = b = 5; c: int = d = a + 1;");
+    let mut vm = VM::new(instructions, constants, symbol_table);
+    let storage = vm.execute().unwrap();
+
+    assert_eq!(storage.get("a"), Some(&5.0));
+    assert_eq!(storage.get("b"), Some(&5.0));
+    assert_eq!(storage.get("c"), Some(&6.0));
+    assert_eq!(storage.get("d"), Some(&6.0));
+}
+
+#[test]
+fn test_vm_dup_and_pop() {
+    let instructions = vec![
+        Instruction::LoadConstant(0),
+        Instruction::Dup,
+        Instruction::StoreVariable("x".to_string()),
+        Instruction::StoreVariable("y".to_string()),
+        Instruction::LoadConstant(1),
+        Instruction::LoadConstant(2),
+        Instruction::Pop,
+        Instruction::StoreVariable("z".to_string()),
+    ];
+    let mut vm = VM::new(instructions, vec![7.0, 1.0, 2.0], SymbolTable::new());
+    let storage = vm.execute().unwrap();
+
+    assert_eq!(storage.get("x"), Some(&7.0));
+    assert_eq!(storage.get("y"), Some(&7.0));
+    assert_eq!(storage.get("z"), Some(&1.0));
+}
+
+#[test]
+fn test_vm_dup_and_pop_underflow() {
+    for instruction in [Instruction::Dup, Instruction::Pop] {
+        let mut vm = VM::new(vec![instruction], vec![], SymbolTable::new());
+        assert_eq!(vm.execute(), Err(VmError::StackUnderflow));
+    }
+}
+
+#[test]
+fn test_expression_statement() {
+    let mut parser = Parser::new(Lexer::new("3 + 4;"));
+    assert_eq!(
+        parser.parse_program(),
+        Ok(ASTNode::Program(vec![ASTNode::ExpressionStatement(
+            boxed_node(ASTNode::BinaryOp {
+                left: boxed_node(ASTNode::Number(3.)),
+                op: BinaryOperator::Add,
+                right: boxed_node(ASTNode::Number(4.)),
+            })
+        )]))
+    );
+
+    let (instructions, constants, symbol_table) = compile("x = 1; x * 2; 3 + 4;");
+    let mut vm = VM::new(instructions, constants, symbol_table);
+
+    assert_eq!(
+        vm.execute().unwrap(),
+        HashMap::from([("x".to_string(), 1.0)])
+    );
+}
+
+#[test]
+fn test_parse_reports_lex_errors() {
+    let mut parser = Parser::new(Lexer::new("x = 0b2;"));
+    assert!(matches!(
+        parser.parse_program(),
+        Err(ParseError::Lex(LexError::MalformedNumber { .. }))
+    ));
+
+    let mut parser = Parser::new(Lexer::new("@"));
+    assert!(matches!(
+        parser.parse_program(),
+        Err(ParseError::Lex(LexError::UnexpectedCharacter { .. }))
+    ));
+}
+
+#[test]
+fn test_unicode_identifiers() {
+    let storage = crate::run("δ = 1; área = δ * 2; x₁ = área + 1; _ünter = x₁;").unwrap();
+    assert_eq!(storage["δ"], 1.0);
+    assert_eq!(storage["área"], 2.0);
+    assert_eq!(storage["x₁"], 3.0);
+    assert_eq!(storage["_ünter"], 3.0);
+
+    assert_eq!(
+        tokenize("日本 = 5;").unwrap()[0],
+        Token::Identifier("日本".to_string())
+    );
+
+    let unexpected = |source: &str| match tokenize(source) {
+        Err(LexError::UnexpectedCharacter { character, .. }) => character,
+        other => panic!("expected an unexpected character, got {:?}", other),
+    };
+    // Combining marks and emoji aren't letters
+    assert_eq!(unexpected("a\u{301} = 1;"), '\u{301}');
+    assert_eq!(unexpected("🙂 = 1;"), '🙂');
+    // Non-ASCII digits neither start a number nor an identifier
+    assert_eq!(unexpected("x = ٣;"), '٣');
+    assert_eq!(unexpected("x = 2²;"), '²');
+}
+
+#[test]
+fn test_non_commutative_operand_order() {
+    let run = |source: &str| crate::run(source).unwrap()["x"];
+
+    assert_eq!(run("x = 10 - 3;"), 7.0);
+    assert_eq!(run("x = 20 / 4;"), 5.0);
+    assert_eq!(run("x = 2 - 3 - 4;"), -5.0);
+    assert_eq!(run("x = 64 / 4 / 2;"), 8.0);
+    assert_eq!(run("x = 17 % 5;"), 2.0);
+}
+
+#[test]
+fn test_long_operator_chain() {
+    let sum = vec!["1"; 1000].join(" + ");
+    assert_eq!(crate::run(&format!("x = {};", sum)).unwrap()["x"], 1000.0);
+
+    let product = vec!["1"; 1000].join(" * ");
+    assert_eq!(crate::run(&format!("x = {};", product)).unwrap()["x"], 1.0);
+}
+
+#[test]
+fn test_floor_division() {
+    let run = |source: &str| crate::run(source).unwrap()["x"];
+
+    assert_eq!(run("x = 7 // 2;"), 3.0);
+    assert_eq!(run("x = -7 // 2;"), -4.0);
+    assert_eq!(run("x = 7 / 2;"), 3.5);
+    assert_eq!(run("x = 1 + 9 // 2 * 2;"), 9.0);
+    assert!(matches!(
+        crate::run("z = 0; x = 7 // z;"),
+        Err(crate::RunError::Vm(VmError::DivisionByZero(
+            BinaryOperator::FloorDiv
+        )))
+    ));
+}
+
+#[test]
+fn test_to_rpn() {
+    let rpn = |source: &str| {
+        to_rpn(
+            &Parser::new(Lexer::new(source))
+                .parse_single_expression()
+                .unwrap(),
+        )
+        .unwrap()
+    };
+
+    assert_eq!(rpn("10 + 5 * 2"), "10 5 2 * +");
+    assert_eq!(rpn("(10 + 5) * 2"), "10 5 + 2 *");
+    assert_eq!(rpn("8 / (4 - (1 + 1))"), "8 4 1 1 + - /");
+    assert_eq!(rpn("a - b - c"), "a b - c -");
+    assert_eq!(rpn("-x + pow(2, 0.5)"), "x neg 2 0.5 pow +");
+    assert_eq!(rpn("a < 1 || b >= 2"), "a 1 < b 2 >= ||");
+
+    let block = Parser::new(Lexer::new("{ a = 1; a + 2 }"))
+        .parse_single_expression()
+        .unwrap();
+    assert_eq!(
+        to_rpn(&block),
+        Err("BlockExpression has no reverse Polish form".to_string())
+    );
+}
+
+#[test]
+fn test_print_tree() {
+    let program = Parser::new(Lexer::new("x = 1 + 2 * y; if (x > 3) { x = -x; }"))
+        .parse_program()
+        .unwrap();
+
+    let expected = "\
+Program
+├── Assignment(x)
+│   └── value: BinaryOp(Add)
+│       ├── left: Number(1)
+│       └── right: BinaryOp(Multiply)
+│           ├── left: Number(2)
+│           └── right: Identifier(y)
+└── If
+    ├── condition: BinaryOp(GreaterThan)
+    │   ├── left: Identifier(x)
+    │   └── right: Number(3)
+    └── then: Block
+        └── Assignment(x)
+            └── value: UnaryOp(Negate)
+                └── operand: Identifier(x)";
+    assert_eq!(print_tree(&program), expected);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_to_json() {
+    let program = Parser::new(Lexer::new(
+        "x = 1.5 + y; z: int = 2; w = 0.30000000000000004;",
+    ))
+    .parse_program()
+    .unwrap();
+    let json = to_json(&program);
+
+    assert!(json.starts_with(r#"{"type":"Program","value":["#));
+    assert!(json.contains(r#""type":"BinaryOp""#));
+    assert!(json.contains(r#""op":"Add""#));
+    assert!(json.contains(r#"{"type":"Float","value":1.5}"#));
+    assert!(json.contains(r#""name":"y""#));
+    assert!(json.contains(r#""declared_type":"Integer""#));
+
+    let parsed: ASTNode = serde_json::from_str(&json).unwrap();
+    assert_eq!(parsed, program);
+}
+
+#[test]
+fn test_ast_stats() {
+    let stats = ast_stats(&ASTNode::Number(1.0));
+    assert_eq!(stats.node_count, 1);
+    assert_eq!(stats.max_depth, 1);
+    assert_eq!(stats.variant_counts, HashMap::from([("Number", 1)]));
+
+    // x = -(1 + y) * 2;
+    let tree = ASTNode::Program(vec![ASTNode::Assignment {
+        variable: "x".to_string(),
+        declared_type: None,
+        constant: false,
+        declaration: false,
+        value: Box::new(ASTNode::BinaryOp {
+            left: Box::new(ASTNode::UnaryOp {
+                op: UnaryOperator::Negate,
+                operand: Box::new(ASTNode::BinaryOp {
+                    left: Box::new(ASTNode::Number(1.0)),
+                    op: BinaryOperator::Add,
+                    right: Box::new(ASTNode::Identifier {
+                        name: "y".to_string(),
+                        span: None,
+                    }),
+                }),
+            }),
+            op: BinaryOperator::Multiply,
+            right: Box::new(ASTNode::Number(2.0)),
+        }),
+        span: None,
+    }]);
+    let stats = ast_stats(&tree);
+    assert_eq!(stats.node_count, 8);
+    assert_eq!(stats.max_depth, 6);
+    assert_eq!(
+        stats.variant_counts,
+        HashMap::from([
+            ("Program", 1),
+            ("Assignment", 1),
+            ("BinaryOp", 2),
+            ("UnaryOp", 1),
+            ("Number", 2),
+            ("Identifier", 1),
+        ])
+    );
+
+    let tree = ASTNode::Block(vec![ASTNode::Break, ASTNode::Continue, ASTNode::Break]);
+    let stats = ast_stats(&tree);
+    assert_eq!(stats.node_count, 4);
+    assert_eq!(stats.max_depth, 2);
+    assert_eq!(stats.variant_counts["Break"], 2);
+}
+
+#[test]
+fn test_to_dot() {
+    let expr = Parser::new(Lexer::new("(1 + x) * -2"))
+        .parse_single_expression()
+        .unwrap();
+    let dot = to_dot(&expr);
+
+    assert!(dot.starts_with("digraph AST {\n"));
+    assert!(dot.ends_with("\n}"));
+    for label in [
+        "n0 [label=\"BinaryOp(Multiply)\"]",
+        "n1 [label=\"BinaryOp(Add)\"]",
+        "n2 [label=\"Number(1)\"]",
+        "n3 [label=\"Identifier(x)\"]",
+        "n4 [label=\"UnaryOp(Negate)\"]",
+        "n5 [label=\"Number(2)\"]",
+    ] {
+        assert!(dot.contains(label), "{}", label);
+    }
+    assert!(dot.contains("n0 -> n1 [label=\"left\"]"));
+    assert!(dot.contains("n0 -> n4 [label=\"right\"]"));
+    assert_eq!(dot.matches("->").count(), 5);
+}
+
+#[test]
+fn test_block_expression() {
+    let storage = crate::run("x = { a = 1; a + 2 }; y = { x * 2 } + 1;").unwrap();
+    assert_eq!(storage["x"], 3.0);
+    assert_eq!(storage["y"], 7.0);
+    assert!(!storage.contains_key("a"));
+
+    // A shadowing declaration is undone when its block ends
+    let storage = crate::run("x = 1; y = { x: float = 2.5; x }; z = x;").unwrap();
+    assert_eq!(storage["x"], 1.0);
+    assert_eq!(storage["y"], 2.5);
+    assert_eq!(storage["z"], 1.0);
+
+    let (instructions, _, _) = compile("x = { 1; 2 };");
+    assert_eq!(
+        instructions
+            .iter()
+            .filter(|x| matches!(x, Instruction::Pop))
+            .count(),
+        1
+    );
+
+    let mut parser = Parser::new(Lexer::new("x = { a = 1; a + 2 }; y = a;"));
+    let program = parser.parse_program().ok().unwrap();
+    let errors = SemanticAnalyzer::new().analyze(&program).unwrap_err();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].error_type, SemanticErrorType::UndefinedVariable);
+}
+
+#[test]
+fn test_block_expression_type() {
+    let mut parser = Parser::new(Lexer::new("x: int = 1; x = { a = 2.5; a };"));
+    let program = parser.parse_program().ok().unwrap();
+    let errors = SemanticAnalyzer::new().analyze(&program).unwrap_err();
+    assert_eq!(errors[0].error_type, SemanticErrorType::TypeMismatch);
+
+    let (_, _, symbol_table) = compile("x = { a = 2.5; a * 2.0 };");
+    assert_eq!(
+        symbol_table.lookup_variable("x").unwrap().symbol_type,
+        Type::Float
+    );
+
+    // Each block's type comes from its own scope, even inside a larger expression
+    let (_, _, symbol_table) = compile("x = { a = 1; a } + { a = 2.5; a }; y = -{ b = 2; b };");
+    assert_eq!(
+        symbol_table.lookup_variable("x").unwrap().symbol_type,
+        Type::Float
+    );
+    assert_eq!(
+        symbol_table.lookup_variable("y").unwrap().symbol_type,
+        Type::Integer
+    );
+}
+
+#[test]
+fn test_const_bindings() {
+    let storage = crate::run("const limit = 10; x = 1; x = limit + x;").unwrap();
+    assert_eq!(storage["x"], 11.0);
+
+    let mut parser = Parser::new(Lexer::new("const limit = 10;\nlimit = 5;"));
+    let program = parser.parse_program().ok().unwrap();
+    let errors = SemanticAnalyzer::new().analyze(&program).unwrap_err();
+    assert_eq!(errors[0].error_type, SemanticErrorType::AssignToConst);
+    assert_eq!(errors[0].span, Some(Span { line: 2, column: 1 }));
+
+    let mut parser = Parser::new(Lexer::new("const limit: float = 2.5; limit += 1.0;"));
+    let program = parser.parse_program().ok().unwrap();
+    let errors = SemanticAnalyzer::new().analyze(&program).unwrap_err();
+    assert_eq!(errors[0].error_type, SemanticErrorType::AssignToConst);
+
+    let mut parser = Parser::new(Lexer::new("const limit += 1;"));
+    assert!(parser.parse_program().is_err());
+}
+
+#[test]
+fn test_power_operator() {
+    let run = |source: &str| crate::run(source).unwrap()["x"];
+
+    assert_eq!(run("x = 2 ^ -1;"), 0.5);
+    assert_eq!(run("x = 9 ^ 0.5;"), 3.0);
+    assert_eq!(run("x = 2 ^ 3 ^ 2;"), 512.0);
+    assert_eq!(run("x = -2 ^ 2;"), -4.0);
+    assert_eq!(run("x = 3 * 2 ^ 2;"), 12.0);
+
+    let mut parser = Parser::new(Lexer::new("2 ^ -1"));
+    assert_eq!(
+        parser.parse_single_expression().unwrap(),
+        ASTNode::BinaryOp {
+            left: Box::new(ASTNode::Number(2.0)),
+            op: BinaryOperator::Power,
+            right: Box::new(ASTNode::UnaryOp {
+                op: UnaryOperator::Negate,
+                operand: Box::new(ASTNode::Number(1.0)),
+            }),
+        }
+    );
+
+    let (instructions, constants, symbol_table) = compile("z = 0; x = z ^ -1;");
+    let mut vm = VM::new(instructions, constants, symbol_table);
+    assert_eq!(vm.execute().unwrap()["x"], f64::INFINITY);
+
+    vm.set_strict_math(true);
+    assert_eq!(vm.execute(), Err(VmError::NonFiniteResult(f64::INFINITY)));
+
+    // Only a non-negative constant exponent keeps an Integer
+    assert_eq!(
+        crate::run_checked("x: int = 2 ^ 3;"),
+        Ok("{x: 8}".to_string())
+    );
+    for source in [
+        "x: int = 2 ^ -1;",
+        "x: int = 9 ^ 0.5;",
+        "y = 2; x: int = 2 ^ y;",
+    ] {
+        let program = Parser::new(Lexer::new(source))
+            .parse_program()
+            .ok()
+            .unwrap();
+        let errors = SemanticAnalyzer::new().analyze(&program).unwrap_err();
+        assert_eq!(errors[0].error_type, SemanticErrorType::TypeMismatch);
+    }
+}
+
+#[test]
+fn test_optimize_instructions_store_load() {
+    let (instructions, constants, symbol_table) = compile("x = y = 5;");
+    assert_eq!(
+        instructions,
+        vec![
+            Instruction::LoadConstant(0),
+            Instruction::StoreVariable("y".to_string()),
+            Instruction::LoadVariable("y".to_string()),
+            Instruction::StoreVariable("x".to_string()),
+            Instruction::Stop,
+        ]
+    );
+
+    let optimized = optimize_instructions(instructions);
+    assert_eq!(
+        optimized,
+        vec![
+            Instruction::LoadConstant(0),
+            Instruction::Dup,
+            Instruction::StoreVariable("y".to_string()),
+            Instruction::StoreVariable("x".to_string()),
+            Instruction::Stop,
+        ]
+    );
+    let storage = VM::new(optimized, constants, symbol_table)
+        .execute()
+        .unwrap();
+    assert_eq!(storage["x"], 5.0);
+    assert_eq!(storage["y"], 5.0);
+
+    // A load that a jump lands on still has to read the variable
+    let jumped_to = vec![
+        Instruction::Jump(2),
+        Instruction::StoreVariable("x".to_string()),
+        Instruction::LoadVariable("x".to_string()),
+        Instruction::Stop,
+    ];
+    assert_eq!(optimize_instructions(jumped_to.clone()), jumped_to);
+}
+
+#[test]
+fn test_bare_expression_result() {
+    let expr = Parser::new(Lexer::new("3 + 4"))
+        .parse_single_expression()
+        .unwrap();
+    let (instructions, constants) = Interpreter::new().generate_instructions(&expr);
+    assert_eq!(instructions.last(), Some(&Instruction::Stop));
+
+    let mut vm = VM::new(instructions, constants, SymbolTable::new());
+    vm.execute().unwrap();
+    assert_eq!(vm.result(), Some(7.0));
+}
+
+#[test]
+fn test_integer_division_option() {
+    let divide = |options: CompileOptions| {
+        let program = Parser::new(Lexer::new("x = 7 / 2;"))
+            .parse_program()
+            .unwrap();
+        let mut analyzer = SemanticAnalyzer::new();
+        analyzer.analyze(&program).unwrap();
+        let (instructions, constants) =
+            Interpreter::with_options(options).generate_instructions(&program);
+        let mut vm = VM::new(instructions, constants, analyzer.symbol_table);
+        vm.execute().unwrap();
+        vm.get("x")
+    };
+
+    assert_eq!(divide(CompileOptions::default()), Some(3.5));
+    assert_eq!(
+        divide(CompileOptions {
+            integer_division: true
+        }),
+        Some(3.0)
+    );
+}
+
+#[test]
+fn test_disassemble_labels() {
+    let (instructions, _, _) = compile("i = 0; while (i < 3) { i += 1; }");
+    let expected = [
+        "    LOAD_CONST #0",
+        "    STORE i",
+        "LABEL_0:",
+        "    LOAD i",
+        "    LOAD_CONST #1",
+        "    LT",
+        "    JUMP_IF_FALSE LABEL_1",
+        "    LOAD i",
+        "    LOAD_CONST #2",
+        "    ADD",
+        "    STORE i",
+        "    JUMP LABEL_0",
+        "LABEL_1:",
+        "    STOP",
+    ]
+    .join("\n");
+    assert_eq!(disassemble(&instructions), expected);
+}
+
+#[test]
+fn test_program_cache() {
+    let mut cache = ProgramCache::new();
+    let first = cache.get_or_compile("x = 1 + 2;").unwrap().to_vec();
+    let second = cache.get_or_compile("x = 1 + 2;").unwrap().to_vec();
+    assert_eq!(first, second);
+    assert_eq!(cache.compile_count(), 1);
+
+    assert_eq!(cache.run("x = 1 + 2;").unwrap()["x"], 3.0);
+    assert_eq!(cache.run("y = 4;").unwrap()["y"], 4.0);
+    assert_eq!(cache.run("y = 4;").unwrap()["y"], 4.0);
+    assert_eq!(cache.compile_count(), 2);
+
+    assert!(matches!(
+        cache.get_or_compile("x = ;"),
+        Err(crate::RunError::Parse(_))
+    ));
+    cache.clear();
+    cache.get_or_compile("x = 1 + 2;").unwrap();
+    assert_eq!(cache.compile_count(), 4);
+}
+
+#[test]
+fn test_run_checked() {
+    assert_eq!(
+        crate::run_checked("x = 2; y = x * 3; z = 2.5;"),
+        Ok("{x: 2, y: 6, z: 2.5}".to_string())
+    );
+
+    let deep = format!("x = {}1;", "(".repeat(100_000));
+    // Trees that are deep without nesting parentheses or blocks
+    let long_chain = format!("x = {};", vec!["1"; 10_000].join(" + "));
+    let else_ifs = format!(
+        "x = 1;{} {{ x = 3; }}",
+        " if (x) { x = 2; } else".repeat(10_000)
+    );
+    let assignments = format!("{}1;", "x = ".repeat(10_000));
+    for source in [
+        "x = 1 +;",
+        "x = 0x;",
+        "x = @;",
+        "x = y;",
+        "x = 1 / 0;",
+        &deep,
+        &long_chain,
+        &else_ifs,
+        &assignments,
+    ] {
+        assert!(crate::run_checked(source).is_err(), "{}", source);
+    }
+    assert_eq!(
+        crate::run_checked("z = 0; x = 1 // z;"),
+        Err("Runtime error: Cannot divide by zero!".to_string())
+    );
+
+    // Unanalyzed code reading an unknown variable is an error, not a panic
+    let mut vm = VM::new(
+        vec![
+            Instruction::LoadVariable("q".to_string()),
+            Instruction::Stop,
+        ],
+        vec![],
+        SymbolTable::new(),
+    );
+    assert_eq!(
+        vm.execute(),
+        Err(VmError::UndefinedVariable("q".to_string()))
+    );
+
+    // So is one indexing past the end of its constant pool
+    let mut vm = VM::new(
+        vec![Instruction::LoadConstant(1), Instruction::Stop],
+        vec![4.0],
+        SymbolTable::new(),
+    );
+    assert_eq!(vm.execute(), Err(VmError::UndefinedConstant(1)));
+}
+
+#[test]
+fn test_render_error() {
+    assert_eq!(
+        crate::render_error("x = 1;\ny = x + z;", Span { line: 2, column: 9 }, "oops"),
+        "2 | y = x + z;\n  |         ^ oops"
+    );
+
+    let source = "a = 1;\nb = 2;\nc = 3;\nd = 4;\ne = 5;\nf = 6;\ng = 7;\nh = 8;\ni = 9;\nj = k;";
+    let err = crate::run(source).unwrap_err();
+    let span = err.span().unwrap();
+    assert_eq!(
+        span,
+        Span {
+            line: 10,
+            column: 5
+        }
+    );
+    assert_eq!(
+        crate::render_error(source, span, "Variable not in scope: k"),
+        "10 | j = k;\n   |     ^ Variable not in scope: k"
+    );
+
+    let err = crate::run("x = (1 + 2;").unwrap_err();
+    assert_eq!(err.span(), Some(Span { line: 1, column: 5 }));
+
+    let Err(crate::RunError::Semantic(errors)) = crate::run("x = 1;\ny = x + 5 / 0;") else {
+        panic!("a zero divisor should be rejected");
+    };
+    assert_eq!(errors[0].message, "Division by zero");
+    assert_eq!(errors[0].span, Some(Span { line: 2, column: 1 }));
+}
+
+#[test]
+fn test_ternary_operator() {
+    let run = |source: &str| crate::run(source).unwrap()["x"];
+
+    assert_eq!(run("a = 3; x = a > 1 ? 1 : 0;"), 1.0);
+    assert_eq!(run("a = 0; x = a > 1 ? 1 : 0;"), 0.0);
+    assert_eq!(run("a = 2; x = a == 1 ? 10 : a == 2 ? 20 : 30;"), 20.0);
+    assert_eq!(run("a = 1; x = (a ? 2 : 3) * 4;"), 8.0);
+
+    // The arm that isn't taken never runs, so its division by zero doesn't fail
+    assert_eq!(run("z = 0; x = z == 0 ? 0 : 1 // z;"), 0.0);
+
+    let mut parser = Parser::new(Lexer::new("a = 1; x = a ? 1 : 2.5;"));
+    let program = parser.parse_program().ok().unwrap();
+    let errors = SemanticAnalyzer::new().analyze(&program).unwrap_err();
+    assert_eq!(errors[0].error_type, SemanticErrorType::TypeMismatch);
+
+    let mut parser = Parser::new(Lexer::new("x = 1 ? 2;"));
+    assert!(parser.parse_program().is_err());
+}
+
+#[test]
+fn test_read_input() {
+    let read = |source: &str, input: &'static str| {
+        let (instructions, constants, symbol_table) = compile(source);
+        VM::new(instructions, constants, symbol_table)
+            .with_input(Box::new(std::io::Cursor::new(input)))
+            .execute()
+    };
+
+    assert_eq!(read("read x;", "42\n").unwrap()["x"], 42.0);
+    assert_eq!(
+        read("read a; read b; c = a * b;", " 2.5\n4\n").unwrap()["c"],
+        10.0
+    );
+    assert_eq!(read("read x;", ""), Err(VmError::EndOfInput));
+    assert_eq!(
+        read("read x;", "abc\n"),
+        Err(VmError::InvalidInput("abc".to_string()))
+    );
+
+    // Input is always a float, and `read` is reserved
+    let mut parser = Parser::new(Lexer::new("x: int = 1; read x;"));
+    let program = parser.parse_program().ok().unwrap();
+    let errors = SemanticAnalyzer::new().analyze(&program).unwrap_err();
+    assert_eq!(errors[0].error_type, SemanticErrorType::TypeMismatch);
+    assert!(Parser::new(Lexer::new("read = 1;"))
+        .parse_program()
+        .is_err());
+}
+
+#[test]
+fn test_delete_variable() {
+    let storage = crate::run("x = 1; y = x + 1; del x;").unwrap();
+    assert!(!storage.contains_key("x"));
+    assert_eq!(storage["y"], 2.0);
+
+    // A deleted name can be declared again, even with a new type
+    let storage = crate::run("x = 1; del x; x = 2.5;").unwrap();
+    assert_eq!(storage["x"], 2.5);
+
+    let analyze = |source: &str| {
+        let program = Parser::new(Lexer::new(source)).parse_program().unwrap();
+        SemanticAnalyzer::with_prelude()
+            .analyze(&program)
+            .unwrap_err()
+    };
+    let errors = analyze("x = 1; del x; y = x;");
+    assert_eq!(errors[0].error_type, SemanticErrorType::UndefinedVariable);
+    let errors = analyze("del x;");
+    assert_eq!(errors[0].error_type, SemanticErrorType::UndefinedVariable);
+    assert_eq!(
+        errors[0].message,
+        "Trying to delete undeclared variable \"x\""
+    );
+    for source in ["const c = 1; del c; c = 2;", "del pi; pi = 3;"] {
+        let errors = analyze(source);
+        assert_eq!(
+            errors[0].error_type,
+            SemanticErrorType::AssignToConst,
+            "{}",
+            source
+        );
+    }
+
+    // Loading a deleted variable fails at runtime too
+    let instructions = vec![
+        Instruction::LoadConstant(0),
+        Instruction::StoreVariable("x".to_string()),
+        Instruction::DeleteVariable("x".to_string()),
+        Instruction::LoadVariable("x".to_string()),
+        Instruction::Stop,
+    ];
+    let mut vm = VM::new(instructions, vec![1.0], SymbolTable::new());
+    assert_eq!(
+        vm.execute(),
+        Err(VmError::UndefinedVariable("x".to_string()))
+    );
+}
+
+#[test]
+fn test_strict_mode_requires_let() {
+    let analyze = |source: &str, strict: bool| {
+        let program = Parser::new(Lexer::new(source)).parse_program().unwrap();
+        let mut analyzer = SemanticAnalyzer::new();
+        analyzer.set_strict_mode(strict);
+        analyzer.analyze(&program)
+    };
+
+    let errors = analyze("y = 3;", true).unwrap_err();
+    assert_eq!(
+        errors[0].error_type,
+        SemanticErrorType::UndeclaredAssignment
+    );
+    assert!(analyze("let y = 3; y = 4;", true).is_ok());
+    assert!(analyze("let y: float = 3.5; const z = 1; y = 4.5;", true).is_ok());
+    assert!(analyze("y = 3;", false).is_ok());
+
+    // `let` declares in the current scope, so it can shadow but not redeclare
+    assert!(analyze("let y = 3; let z = { let y = 4.5; y };", true).is_ok());
+    let errors = analyze("let y = 3; let y = 4;", true).unwrap_err();
+    assert_eq!(
+        errors[0].error_type,
+        SemanticErrorType::DuplicateDeclaration
+    );
+
+    let storage = crate::run("let y = 3; y = y + 1;").unwrap();
+    assert_eq!(storage["y"], 4.0);
+    assert!(Parser::new(Lexer::new("let y += 1;"))
+        .parse_program()
+        .is_err());
+}
+
+#[test]
+fn test_break_and_continue() {
+    let storage = crate::run("i = 0; while (1) { i += 1; if (i == 5) { break; } }").unwrap();
+    assert_eq!(storage["i"], 5.0);
+
+    // Sums the odd numbers below 10
+    let storage = crate::run(
+        "i = 0; sum = 0; while (i < 10) { i += 1; if (i % 2 == 0) { continue; } sum += i; }",
+    )
+    .unwrap();
+    assert_eq!(storage["sum"], 25.0);
+
+    // Only the inner loop is left
+    let storage = crate::run(
+        "n = 0; i = 0; while (i < 3) { i += 1; j = 0; while (1) { j += 1; n += 1; if (j == 2) { break; } } }",
+    )
+    .unwrap();
+    assert_eq!(storage["n"], 6.0);
+
+    for source in [
+        "break;",
+        "if (1) { continue; }",
+        "while (1) { x = { break; 1 }; }",
+    ] {
+        let program = Parser::new(Lexer::new(source)).parse_program().unwrap();
+        let errors = SemanticAnalyzer::new().analyze(&program).unwrap_err();
+        assert_eq!(
+            errors[0].error_type,
+            SemanticErrorType::LoopControlOutsideLoop,
+            "{}",
+            source
+        );
+    }
+}
+
+#[test]
+fn test_optimizer_removes_dead_assignments() {
+    let optimized = |source: &str| {
+        let program = Parser::new(Lexer::new(source)).parse_program().unwrap();
+        strip_spans(&optimize(&program))
+    };
+    let parsed =
+        |source: &str| strip_spans(&Parser::new(Lexer::new(source)).parse_program().unwrap());
+
+    assert_eq!(optimized("x = 1; x = 2;"), parsed("x = 2;"));
+    assert_eq!(optimized("x = 1; y = 3; x = 2;"), parsed("y = 3; x = 2;"));
+    assert_eq!(optimized("x = 1; x = 2; x = 3;"), parsed("x = 3;"));
+
+    // Kept whenever the first value might be read
+    for source in [
+        "x = 1; y = x; x = 2;",
+        "x = 1; x = x + 1;",
+        "x = 1; if (y) { z = x; } x = 2;",
+        "x = 1; if (y) { x = 2; }",
+        "x = 1; x: int = 2;",
+        "x = y = 1; x = 2;",
+    ] {
+        assert_eq!(optimized(source), parsed(source), "{}", source);
+    }
+}
+
+#[test]
+fn test_parse_error_chained_comparison() {
+    let mut parser = Parser::new(Lexer::new("x = 3;\ny = 1 < x < 5;"));
+    let err = parser.parse_program().unwrap_err();
+    assert_eq!(
+        err,
+        ParseError::ChainedComparison {
+            first: BinaryOperator::LessThan,
+            second: BinaryOperator::LessThan,
+            span: Span {
+                line: 2,
+                column: 11
+            },
+        }
+    );
+    assert!(err.to_string().contains("`a < b && b < c`"));
+
+    let mut parser = Parser::new(Lexer::new("a == b != c"));
+    assert!(matches!(
+        parser.parse_single_expression(),
+        Err(ParseError::ChainedComparison { .. })
+    ));
+
+    assert_eq!(crate::run("x = 3; y = 1 < x && x < 5;").unwrap()["y"], 1.0);
+}
+
+#[test]
+fn test_format_options() {
+    let defaults = FormatOptions::default();
+    assert_eq!(defaults.format(1e-10), "1e-10");
+    assert_eq!(defaults.format(-2.5e20), "-2.5e20");
+    assert_eq!(defaults.format(1234.5), "1234.5");
+    assert_eq!(defaults.format(0.001), "0.001");
+    assert_eq!(defaults.format(0.0), "0");
+
+    let options = FormatOptions {
+        scientific_threshold: 3,
+        precision: Some(2),
+    };
+    assert_eq!(options.format(12345.678), "1.23e4");
+    assert_eq!(options.format(5.436), "5.44");
+
+    let (instructions, constants, symbol_table) = compile("x = 0.00000000001; y = 2.5;");
+    let mut vm = VM::new(instructions, constants, symbol_table);
+    vm.execute().unwrap();
+    assert_eq!(vm.format_storage(), "{x: 1e-11, y: 2.5}");
+}
+
+// Trees from the layered parse_term/parse_factor parser, written in RPN, which the
+// binding power table has to reproduce exactly
+#[test]
+fn test_pratt_parser_matches_layered_parser() {
+    let cases = [
+        ("1 + 2 * 3", "1 2 3 * +"),
+        ("(1 + 2) * 3", "1 2 + 3 *"),
+        ("a - b - c", "a b - c -"),
+        ("a / b // c % d * e", "a b / c // d % e *"),
+        ("a + b - c * d / e % f", "a b + c d * e / f % -"),
+        ("2 ^ 3 ^ 2", "2 3 2 ^ ^"),
+        ("-2 ^ 2", "2 2 ^ neg"),
+        ("2 ^ -1", "2 1 neg ^"),
+        ("2 ^ -1 ^ 2", "2 1 2 ^ neg ^"),
+        ("-a * b", "a neg b *"),
+        ("- -a", "a neg neg"),
+        ("-(a + b) * -c", "a b + neg c neg *"),
+        ("a < b + 1", "a b 1 + <"),
+        ("(a < b) < c", "a b < c <"),
+        ("x <= -y ^ 2 * 3", "x y 2 ^ neg 3 * <="),
+        ("a == b && c != d || e", "a b == c d != && e ||"),
+        ("a || b && c", "a b c && ||"),
+        ("a && b || c && d", "a b && c d && ||"),
+        ("1 + 2 < 3 * 4 && x >= y", "1 2 + 3 4 * < x y >= &&"),
+        ("a ? b : c ? d : e", "a b c d e ?: ?:"),
+        ("a || b ? c + 1 : d", "a b || c 1 + d ?:"),
+        ("1 ? (a ? 2 : 3) : 4", "1 a 2 3 ?: 4 ?:"),
+        ("pow(a, 2) ^ 2 + abs(-b)", "a 2 pow 2 ^ b neg abs +"),
+        (
+            "max(1 + 2, min(a, b) * 2) - sqrt(4) ^ 0.5",
+            "1 2 + a b min 2 * max 4 sqrt 0.5 ^ -",
+        ),
+    ];
+
+    for (source, expected) in cases {
+        let expr = Parser::new(Lexer::new(source))
+            .parse_single_expression()
+            .unwrap();
+        assert_eq!(to_rpn(&expr).unwrap(), expected, "{}", source);
+    }
+}
+
+#[test]
+fn test_uninitialized_variables() {
+    // The VM can still be handed a symbol table that disagrees with its storage
+    let mut symbol_table = SymbolTable::new();
+    symbol_table
+        .declare_variable(&"x".to_string(), Type::Integer)
+        .unwrap();
+    let mut vm = VM::new(
+        vec![
+            Instruction::LoadVariable("x".to_string()),
+            Instruction::StoreVariable("y".to_string()),
+            Instruction::Stop,
+        ],
+        vec![],
+        symbol_table,
+    );
+    assert_eq!(
+        vm.execute(),
+        Err(VmError::UninitializedVariable("x".to_string()))
+    );
+
+    // The analyzer rejects reads until the variable is assigned
+    let analyze = |source: &str| {
+        let mut analyzer = SemanticAnalyzer::new();
+        analyzer
+            .symbol_table
+            .declare_uninitialized(&"x".to_string(), Type::Integer)
+            .unwrap();
+        analyzer.analyze(&Parser::new(Lexer::new(source)).parse_program().unwrap())
+    };
+    let errors = analyze("y = x + 1;").unwrap_err();
+    assert_eq!(
+        errors[0].error_type,
+        SemanticErrorType::UninitializedVariable
+    );
+    assert!(analyze("x = 1; y = x + 1;").is_ok());
+
+    // A variable assigned in only some branches, or in a loop body, might not be set
+    for source in [
+        "x = 1; if (0) { y = 2; } z = y;",
+        "x = 1; if (x > 0) { y = 2; } else { w = 3; } z = y;",
+        "x = 1; if (x > 0) { y = 2; } else { z = y; }",
+        "x = 0; while (x < 3) { y = x; x = x + 1; } z = y;",
+    ] {
+        let errors = analyze(source).unwrap_err();
+        assert_eq!(
+            errors[0].error_type,
+            SemanticErrorType::UninitializedVariable,
+            "{}",
+            source
+        );
+    }
+    assert!(analyze("x = 1; if (x > 0) { y = 2; } else { y = 3; } z = y;").is_ok());
+}
+
+#[test]
+fn test_unary_operator_types() {
+    let analyze = |source: &str| {
+        let program = Parser::new(Lexer::new(source)).parse_program().unwrap();
+        SemanticAnalyzer::new().analyze(&program)
+    };
+
+    // There are no boolean literals, so comparisons stand in for `true`
+    for source in ["x = -(1 < 2);", "x = !5;", "x = !2.5;"] {
+        let errors = analyze(source).unwrap_err();
+        assert_eq!(
+            errors[0].error_type,
+            SemanticErrorType::TypeMismatch,
+            "{}",
+            source
+        );
+    }
+    for source in ["x = -5;", "x = -2.5;", "flag = 1 < 2; x = !flag;"] {
+        assert!(analyze(source).is_ok(), "{}", source);
+    }
+
+    let run = |source: &str| crate::run(source).unwrap()["x"];
+    assert_eq!(run("a = 3; x = !(a > 5);"), 1.0);
+    assert_eq!(run("a = 3; x = !(a > 1) || a == 3;"), 1.0);
+    assert_eq!(run("a = 3; x = !(a > 1 && a != 2);"), 0.0);
+    assert_eq!(run("a = 1; x = 2 != a ? 7 : 8;"), 7.0);
+}
+
+#[test]
+fn test_run_repl_result() {
+    let (storage, result) = crate::run_repl("x = 2; x + 3").unwrap();
+    assert_eq!(storage, HashMap::from([("x".to_string(), 2.0)]));
+    assert_eq!(result, Some(5.0));
+
+    // Only a trailing expression statement counts as the result
+    assert_eq!(crate::run_repl("3 + 4;").unwrap().1, Some(7.0));
+    assert_eq!(crate::run_repl("x = 2; x + 1; y = x;").unwrap().1, None);
+    assert_eq!(crate::run_repl("x = 2;").unwrap().1, None);
+}
+
+// Collects what a VM prints so a test can read it back after the run
+#[derive(Clone, Default)]
+struct SharedOutput(Rc<RefCell<Vec<u8>>>);
+
+impl std::io::Write for SharedOutput {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_left_to_right_evaluation_order() {
+    let printed = |source: &str, optimized: bool| {
+        let program = Parser::new(Lexer::new(source)).parse_program().unwrap();
+        let program = if optimized {
+            optimize(&program)
+        } else {
+            program
+        };
+        let mut analyzer = SemanticAnalyzer::new();
+        analyzer.analyze(&program).unwrap();
+        let (instructions, constants) = Interpreter::new().generate_instructions(&program);
+
+        let output = SharedOutput::default();
+        VM::new(instructions, constants, analyzer.symbol_table)
+            .with_output(Box::new(output.clone()))
+            .execute()
+            .unwrap();
+        String::from_utf8(output.0.take()).unwrap()
+    };
+
+    assert_eq!(printed("print(1) + print(2);", false), "1\n2\n");
+    // Operands run in source order however the operators group them
+    assert_eq!(
+        printed("x = print(1) - print(2) * print(3) ^ print(4);", false),
+        "1\n2\n3\n4\n"
+    );
+    assert_eq!(printed("x = max(print(5), print(6));", false), "5\n6\n");
+    assert_eq!(
+        printed("x = print(1) < print(2) ? print(3) : print(4);", false),
+        "1\n2\n3\n"
+    );
+    // Identities and dead assignments that would skip a print are kept
+    assert_eq!(
+        printed("x = print(7) * 0; x = print(8) + 0;", true),
+        "7\n8\n"
+    );
+}
+
+#[test]
+fn test_transcendental_builtins_follow_math_mode() {
+    let run = |mode: MathMode| {
+        let (instructions, constants, symbol_table) =
+            compile("a = sin(2.5); b = cos(2.5); c = exp(2.5); d = ln(2.5); f = 2.5 ^ 1.5;");
+        let mut vm = VM::new(instructions, constants, symbol_table);
+        vm.set_math_mode(mode);
+        vm.execute().unwrap()
+    };
+    let functions = [
+        ("a", math::sin as fn(MathMode, f64) -> f64),
+        ("b", math::cos),
+        ("c", math::exp),
+        ("d", math::ln),
+    ];
+
+    for mode in [MathMode::Native, MathMode::Deterministic] {
+        let storage = run(mode);
+        for (name, function) in functions {
+            assert_eq!(
+                storage[name].to_bits(),
+                function(mode, 2.5).to_bits(),
+                "{}",
+                name
+            );
+        }
+        assert_eq!(storage["f"].to_bits(), math::pow(mode, 2.5, 1.5).to_bits());
+    }
+}
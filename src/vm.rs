@@ -1,99 +1,653 @@
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    fmt,
+    io::{self, BufRead, Write},
+};
 
+use crate::ast::BinaryOperator;
 use crate::interpreter::Instruction;
+use crate::lexer::Span;
+use crate::math::{self, MathMode};
+use crate::prelude::PRELUDE_CONSTANTS;
 use crate::semantic_analyzer::SymbolTable;
 
+// How an arithmetic operator behaves when its operands are invalid (e.g. a zero divisor)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArithmeticPolicy {
+    // Halt the program with an error
+    Error,
+    // Follow IEEE 754 and produce inf/NaN
+    Ieee,
+}
+
+// Calls nested deeper than this fail unless the VM is configured otherwise
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 256;
+
+// Likewise for the number of values on the operand stack
+pub const DEFAULT_MAX_STACK_DEPTH: usize = 10_000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum VmError {
+    // A Call would nest deeper than the maximum call depth
+    CallStackOverflow(usize),
+    // An instruction pushed past the maximum operand stack depth
+    StackOverflow(usize),
+    // A Return was reached outside of any Call
+    ReturnWithoutCall,
+    // A zero divisor for an operator whose policy is ArithmeticPolicy::Error
+    DivisionByZero(BinaryOperator),
+    // An instruction needed more values than the operand stack held
+    StackUnderflow,
+    // Strict math is on and an instruction produced inf or NaN
+    NonFiniteResult(f64),
+    // A LoadConstant whose index is past the end of the constant pool
+    UndefinedConstant(usize),
+    // A LoadVariable for a name that was never stored, only possible for unanalyzed code
+    UndefinedVariable(String),
+    // A LoadVariable for a declared variable that was never stored
+    UninitializedVariable(String),
+    // A ReadInput found no more lines to read
+    EndOfInput,
+    // A ReadInput line that isn't a number, or the error that stopped it being read
+    InvalidInput(String),
+    // A Print whose output could not be written
+    OutputFailed(String),
+}
+
+impl fmt::Display for VmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VmError::CallStackOverflow(depth) => {
+                write!(f, "Call stack overflow, exceeded depth of {}", depth)
+            }
+            VmError::StackOverflow(depth) => {
+                write!(f, "Stack overflow, exceeded depth of {}", depth)
+            }
+            VmError::ReturnWithoutCall => write!(f, "Cannot return without a call!"),
+            VmError::DivisionByZero(BinaryOperator::Modulo) => {
+                write!(f, "Cannot take modulo by zero!")
+            }
+            VmError::DivisionByZero(_) => write!(f, "Cannot divide by zero!"),
+            VmError::StackUnderflow => write!(f, "Stack is empty!"),
+            VmError::NonFiniteResult(x) => write!(f, "Result {} is not a finite number!", x),
+            VmError::UndefinedConstant(x) => write!(f, "Constant not in the pool! (#{})", x),
+            VmError::UndefinedVariable(x) => write!(f, "Variable not in storage! ({})", x),
+            VmError::UninitializedVariable(x) => {
+                write!(f, "Variable was declared but never assigned! ({})", x)
+            }
+            VmError::EndOfInput => write!(f, "Ran out of input to read!"),
+            VmError::InvalidInput(x) => write!(f, "Could not read a number from input! ({})", x),
+            VmError::OutputFailed(x) => write!(f, "Could not write output! ({})", x),
+        }
+    }
+}
+
+// How format_storage prints values. Values whose decimal exponent is at least
+// `scientific_threshold` in size, like 1e-10 or 1e20 with the default of 10, are
+// printed in scientific notation. `precision` is the number of digits after the
+// point, or None for as many as the value needs.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FormatOptions {
+    pub scientific_threshold: i32,
+    pub precision: Option<usize>,
+}
+
+impl Default for FormatOptions {
+    fn default() -> Self {
+        FormatOptions {
+            scientific_threshold: 10,
+            precision: None,
+        }
+    }
+}
+
+impl FormatOptions {
+    pub fn format(&self, value: f64) -> String {
+        let exponent = match value {
+            0.0 => 0,
+            x if x.is_finite() => x.abs().log10().floor() as i32,
+            _ => 0,
+        };
+
+        match (exponent.abs() >= self.scientific_threshold, self.precision) {
+            (true, Some(precision)) => format!("{:.*e}", precision, value),
+            (true, None) => format!("{:e}", value),
+            (false, Some(precision)) => format!("{:.*}", precision, value),
+            (false, None) => value.to_string(),
+        }
+    }
+}
+
+// Called before each instruction runs with the program counter, the instruction and the stack
+pub type TraceHook = Box<dyn FnMut(usize, &Instruction, &[f64])>;
+
+// One active call: where to resume afterwards and the variables stored during it
+struct Frame {
+    return_address: usize,
+    locals: HashMap<String, f64>,
+}
+
 pub struct VM {
     instructions: Vec<Instruction>,
+    constants: Vec<f64>,
     symbol_table: SymbolTable,
     storage: HashMap<String, f64>,
+    policies: HashMap<BinaryOperator, ArithmeticPolicy>,
+    prelude: bool,
+    math_mode: MathMode,
+    strict_math: bool,
+    // Every value stored into each watched variable, in order
+    watched: HashMap<String, Vec<f64>>,
     program_counter: usize,
     stack: Vec<f64>,
+    call_stack: Vec<Frame>,
+    // Open block expression scopes, innermost last, each with the names it
+    // declared and the values to restore for them on exit
+    scopes: Vec<Vec<(String, Option<f64>)>>,
+    max_call_depth: usize,
+    max_stack_depth: usize,
+    // Source position of each instruction, empty unless set_line_table was called
+    line_table: Vec<Option<Span>>,
+    trace: Option<TraceHook>,
+    // Where `read` takes its lines from, stdin unless with_input was used
+    input: Option<Box<dyn BufRead>>,
+    // Where `print` writes, stdout unless with_output was used
+    output: Option<Box<dyn Write>>,
 }
 
 impl VM {
-    pub fn new(instructions: Vec<Instruction>, symbol_table: SymbolTable) -> VM {
+    // `constants` is the pool that LoadConstant instructions index into
+    pub fn new(
+        instructions: Vec<Instruction>,
+        constants: Vec<f64>,
+        symbol_table: SymbolTable,
+    ) -> VM {
+        VM::with_policies(instructions, constants, symbol_table, HashMap::new())
+    }
+
+    // Operators missing from `policies` fall back to ArithmeticPolicy::Error
+    pub fn with_policies(
+        instructions: Vec<Instruction>,
+        constants: Vec<f64>,
+        symbol_table: SymbolTable,
+        policies: HashMap<BinaryOperator, ArithmeticPolicy>,
+    ) -> VM {
         let storage = HashMap::new();
         VM {
             instructions,
+            constants,
             symbol_table,
             storage,
+            policies,
+            prelude: false,
+            math_mode: MathMode::Native,
+            strict_math: false,
+            watched: HashMap::new(),
             program_counter: 0,
             stack: vec![],
+            call_stack: vec![],
+            scopes: vec![],
+            max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+            max_stack_depth: DEFAULT_MAX_STACK_DEPTH,
+            line_table: vec![],
+            trace: None,
+            input: None,
+            output: None,
+        }
+    }
+
+    // Seeds storage with the prelude constants, to pair with SemanticAnalyzer::with_prelude
+    pub fn with_prelude(
+        instructions: Vec<Instruction>,
+        constants: Vec<f64>,
+        symbol_table: SymbolTable,
+    ) -> VM {
+        let mut vm = VM::new(instructions, constants, symbol_table);
+        vm.prelude = true;
+        vm
+    }
+
+    // Reads `read` statements' input from `reader`, e.g. a `Cursor` over a string in tests
+    pub fn with_input(mut self, reader: Box<dyn BufRead>) -> VM {
+        self.input = Some(reader);
+        self
+    }
+
+    // Sends `print` output to `writer` instead of stdout
+    pub fn with_output(mut self, writer: Box<dyn Write>) -> VM {
+        self.output = Some(writer);
+        self
+    }
+
+    // Records the history of `names` on subsequent executions
+    pub fn watch(&mut self, names: &[&str]) {
+        for name in names {
+            self.watched.insert(name.to_string(), vec![]);
         }
     }
 
-    pub fn execute(&mut self) -> HashMap<String, f64> {
+    // Deterministic mode makes builtins like pow give identical results on every platform
+    pub fn set_math_mode(&mut self, mode: MathMode) {
+        self.math_mode = mode;
+    }
+
+    // Strict math turns any inf or NaN result into VmError::NonFiniteResult
+    pub fn set_strict_math(&mut self, strict: bool) {
+        self.strict_math = strict;
+    }
+
+    pub fn set_max_call_depth(&mut self, depth: usize) {
+        self.max_call_depth = depth;
+    }
+
+    pub fn set_max_stack_depth(&mut self, depth: usize) {
+        self.max_stack_depth = depth;
+    }
+
+    // Pairs with Interpreter::generate_with_line_table
+    pub fn set_line_table(&mut self, line_table: Vec<Option<Span>>) {
+        self.line_table = line_table;
+    }
+
+    // Source position of the instruction most recently started, e.g. the one that failed
+    pub fn current_span(&self) -> Option<Span> {
+        let index = self.program_counter.checked_sub(1)?;
+        self.line_table.get(index).copied().flatten()
+    }
+
+    pub fn set_trace(&mut self, trace: TraceHook) {
+        self.trace = Some(trace);
+    }
+
+    pub fn watch_history(&self) -> &HashMap<String, Vec<f64>> {
+        &self.watched
+    }
+
+    pub fn storage(&self) -> &HashMap<String, f64> {
+        &self.storage
+    }
+
+    // Value of a single variable from the last run, without copying the storage
+    pub fn get(&self, name: &str) -> Option<f64> {
+        self.storage.get(name).copied()
+    }
+
+    // Top of the operand stack after a run, e.g. the value of a compiled bare expression
+    pub fn result(&self) -> Option<f64> {
+        self.stack.last().copied()
+    }
+
+    pub fn variables(&self) -> impl Iterator<Item = (&String, &f64)> {
+        self.storage.iter()
+    }
+
+    // Storage sorted by name, with whole numbers printed without a trailing `.0`
+    pub fn format_storage(&self) -> String {
+        self.format_storage_with(&FormatOptions::default())
+    }
+
+    pub fn format_storage_with(&self, options: &FormatOptions) -> String {
+        let mut entries: Vec<(&String, &f64)> = self.storage.iter().collect();
+        entries.sort_by(|a, b| a.0.cmp(b.0));
+
+        let formatted: Vec<String> = entries
+            .iter()
+            .map(|(name, value)| format!("{}: {}", name, options.format(**value)))
+            .collect();
+        format!("{{{}}}", formatted.join(", "))
+    }
+
+    pub fn symbol_table(&self) -> &SymbolTable {
+        &self.symbol_table
+    }
+
+    // Clears everything left by a previous run, keeping the program and settings
+    pub fn reset(&mut self) {
         self.storage.clear();
+        for history in self.watched.values_mut() {
+            history.clear();
+        }
         self.program_counter = 0;
-        self.stack = vec![];
+        self.stack.clear();
+        self.call_stack.clear();
+        self.scopes.clear();
+    }
+
+    // Swaps in a new program, reusing this VM's settings and allocations
+    pub fn load(
+        &mut self,
+        instructions: Vec<Instruction>,
+        constants: Vec<f64>,
+        symbol_table: SymbolTable,
+    ) {
+        self.instructions = instructions;
+        self.constants = constants;
+        self.symbol_table = symbol_table;
+        self.line_table.clear();
+        self.reset();
+    }
+
+    pub fn execute(&mut self) -> Result<HashMap<String, f64>, VmError> {
+        self.execute_with_inputs(HashMap::new())
+    }
+
+    // Runs the program with `inputs` already in storage, e.g. the storage of a previous run
+    pub fn execute_with_inputs(
+        &mut self,
+        inputs: HashMap<String, f64>,
+    ) -> Result<HashMap<String, f64>, VmError> {
+        self.reset();
+        if self.prelude {
+            for (name, value) in PRELUDE_CONSTANTS {
+                self.storage.insert(name.to_string(), value);
+            }
+        }
+        self.storage.extend(inputs);
 
         while self.program_counter < self.instructions.len() {
-            self.evaluate_next_instruction();
-            self.program_counter += 1;
+            self.evaluate_next_instruction()?;
+            self.check_finite()?;
+            self.check_stack_depth()?;
         }
 
-        self.storage.clone()
+        Ok(self.storage.clone())
+    }
+
+    // Values are checked as they are pushed, so only the top of the stack can be new
+    fn check_finite(&self) -> Result<(), VmError> {
+        match self.stack.last() {
+            Some(x) if self.strict_math && !x.is_finite() => Err(VmError::NonFiniteResult(*x)),
+            _ => Ok(()),
+        }
     }
 
-    fn evaluate_next_instruction(&mut self) {
+    // No instruction pushes more than one value, so checking after each one is enough
+    fn check_stack_depth(&self) -> Result<(), VmError> {
+        if self.stack.len() > self.max_stack_depth {
+            return Err(VmError::StackOverflow(self.max_stack_depth));
+        }
+        Ok(())
+    }
+
+    fn evaluate_next_instruction(&mut self) -> Result<(), VmError> {
         if let Some(instruction) = self.instructions.get(self.program_counter) {
+            if let Some(trace) = self.trace.as_mut() {
+                trace(self.program_counter, instruction, &self.stack);
+            }
+            self.program_counter += 1;
             match instruction {
-                Instruction::LoadConstant(x) => {
-                    self.stack.push(x.clone());
+                Instruction::LoadConstant(index) => {
+                    let val = self
+                        .constants
+                        .get(*index)
+                        .ok_or(VmError::UndefinedConstant(*index))?;
+                    self.stack.push(*val);
                 }
                 Instruction::LoadVariable(x) => {
-                    if let None = self.symbol_table.lookup_variable(x) {
-                        panic!("Variable not in scope! ({})", x)
+                    // Locals of the active call shadow globals
+                    if let Some(val) = self.call_stack.last().and_then(|f| f.locals.get(x)) {
+                        self.stack.push(*val);
+                        return Ok(());
                     }
 
-                    if let Some(val) = self.storage.get(x) {
-                        self.stack.push(val.clone());
-                    } else {
-                        panic!("Variable was in scope, but somehow not in storage! ({})", x)
+                    // Storage is checked first, since variables from block expressions are
+                    // no longer in the symbol table's scopes by the time the program runs
+                    match self.storage.get(x) {
+                        Some(val) => self.stack.push(*val),
+                        None if self.symbol_table.lookup_variable(x).is_some() => {
+                            return Err(VmError::UninitializedVariable(x.clone()))
+                        }
+                        None => return Err(VmError::UndefinedVariable(x.clone())),
                     }
                 }
                 Instruction::StoreVariable(x) => {
-                    if let Some(val) = self.stack.pop() {
-                        self.storage.insert(x.clone(), val);
-                    } else {
-                        panic!("Stack is empty, cannot store variable \"{}\" !", x)
+                    let val = self.stack.pop().ok_or(VmError::StackUnderflow)?;
+                    store(
+                        x,
+                        val,
+                        active_variables(&mut self.call_stack, &mut self.storage),
+                        &mut self.scopes,
+                        &mut self.watched,
+                    );
+                }
+                Instruction::ReadInput(x) => {
+                    let reader = self
+                        .input
+                        .get_or_insert_with(|| Box::new(io::stdin().lock()));
+                    let val = read_number(reader.as_mut())?;
+                    store(
+                        x,
+                        val,
+                        active_variables(&mut self.call_stack, &mut self.storage),
+                        &mut self.scopes,
+                        &mut self.watched,
+                    );
+                }
+                Instruction::DeleteVariable(x) => {
+                    active_variables(&mut self.call_stack, &mut self.storage).remove(x);
+                }
+                Instruction::EnterScope(names) => {
+                    let variables = active_variables(&mut self.call_stack, &mut self.storage);
+                    let saved = names
+                        .iter()
+                        .map(|name| (name.clone(), variables.get(name).copied()))
+                        .collect();
+                    self.scopes.push(saved);
+                }
+                Instruction::ExitScope => {
+                    let saved = self.scopes.pop().unwrap_or_default();
+                    let variables = active_variables(&mut self.call_stack, &mut self.storage);
+                    // Newest first, so a name saved twice ends with its oldest value
+                    for (name, value) in saved.into_iter().rev() {
+                        match value {
+                            Some(value) => variables.insert(name, value),
+                            None => variables.remove(&name),
+                        };
                     }
                 }
                 Instruction::Add => {
-                    let (n1, n2) = self.pop_two();
+                    let (n1, n2) = self.pop_two()?;
                     self.stack.push(n2 + n1);
                 }
                 Instruction::Subtract => {
-                    let (n1, n2) = self.pop_two();
+                    let (n1, n2) = self.pop_two()?;
                     self.stack.push(n2 - n1);
                 }
                 Instruction::Multiply => {
-                    let (n1, n2) = self.pop_two();
+                    let (n1, n2) = self.pop_two()?;
                     self.stack.push(n2 * n1);
                 }
                 Instruction::Divide => {
-                    let (n1, n2) = self.pop_two();
-                    if n2 == 0.0 {
-                        panic!("Cannot divide by zero!")
+                    let (n1, n2) = self.pop_two()?;
+                    if n1 == 0.0
+                        && self.policy_for(&BinaryOperator::Divide) == ArithmeticPolicy::Error
+                    {
+                        return Err(VmError::DivisionByZero(BinaryOperator::Divide));
                     }
                     self.stack.push(n2 / n1);
                 }
+                Instruction::FloorDivide => {
+                    let (n1, n2) = self.pop_two()?;
+                    if n1 == 0.0
+                        && self.policy_for(&BinaryOperator::FloorDiv) == ArithmeticPolicy::Error
+                    {
+                        return Err(VmError::DivisionByZero(BinaryOperator::FloorDiv));
+                    }
+                    self.stack.push((n2 / n1).floor());
+                }
+                Instruction::Modulo => {
+                    let (n1, n2) = self.pop_two()?;
+                    if n1 == 0.0
+                        && self.policy_for(&BinaryOperator::Modulo) == ArithmeticPolicy::Error
+                    {
+                        return Err(VmError::DivisionByZero(BinaryOperator::Modulo));
+                    }
+                    self.stack.push(n2 % n1);
+                }
+                Instruction::Sqrt => {
+                    let x = self.pop_one()?;
+                    self.stack.push(x.sqrt());
+                }
+                Instruction::Abs => {
+                    let x = self.pop_one()?;
+                    self.stack.push(x.abs());
+                }
+                Instruction::PowBuiltin => {
+                    let (exponent, base) = self.pop_two()?;
+                    self.stack.push(math::pow(self.math_mode, base, exponent));
+                }
+                Instruction::Min => {
+                    let (n1, n2) = self.pop_two()?;
+                    self.stack.push(n2.min(n1));
+                }
+                Instruction::Max => {
+                    let (n1, n2) = self.pop_two()?;
+                    self.stack.push(n2.max(n1));
+                }
+                Instruction::Sin => {
+                    let x = self.pop_one()?;
+                    self.stack.push(math::sin(self.math_mode, x));
+                }
+                Instruction::Cos => {
+                    let x = self.pop_one()?;
+                    self.stack.push(math::cos(self.math_mode, x));
+                }
+                Instruction::Exp => {
+                    let x = self.pop_one()?;
+                    self.stack.push(math::exp(self.math_mode, x));
+                }
+                Instruction::Ln => {
+                    let x = self.pop_one()?;
+                    self.stack.push(math::ln(self.math_mode, x));
+                }
+                Instruction::Print => {
+                    let x = *self.stack.last().ok_or(VmError::StackUnderflow)?;
+                    let writer = self.output.get_or_insert_with(|| Box::new(io::stdout()));
+                    writeln!(writer, "{}", FormatOptions::default().format(x))
+                        .map_err(|err| VmError::OutputFailed(err.to_string()))?;
+                }
+                Instruction::Equal => self.compare(|n2, n1| n2 == n1)?,
+                Instruction::NotEqual => self.compare(|n2, n1| n2 != n1)?,
+                Instruction::LessThan => self.compare(|n2, n1| n2 < n1)?,
+                Instruction::GreaterThan => self.compare(|n2, n1| n2 > n1)?,
+                Instruction::LessEqual => self.compare(|n2, n1| n2 <= n1)?,
+                Instruction::GreaterEqual => self.compare(|n2, n1| n2 >= n1)?,
+                Instruction::Negate => {
+                    let val = self.pop_one()?;
+                    self.stack.push(-val);
+                }
+                Instruction::Not => {
+                    let val = self.pop_one()?;
+                    self.stack.push(f64::from(val == 0.0));
+                }
+                Instruction::Dup => {
+                    let val = self.pop_one()?;
+                    self.stack.extend([val, val]);
+                }
+                Instruction::Pop => {
+                    self.pop_one()?;
+                }
+                Instruction::Jump(target) => {
+                    self.program_counter = *target;
+                }
+                Instruction::JumpIfFalse(target) => {
+                    if self.stack.pop().ok_or(VmError::StackUnderflow)? == 0.0 {
+                        self.program_counter = *target;
+                    }
+                }
+                Instruction::Call(target) => {
+                    if self.call_stack.len() >= self.max_call_depth {
+                        return Err(VmError::CallStackOverflow(self.max_call_depth));
+                    }
+                    self.call_stack.push(Frame {
+                        return_address: self.program_counter,
+                        locals: HashMap::new(),
+                    });
+                    self.program_counter = *target;
+                }
+                Instruction::Return => {
+                    // The return value stays on top of the operand stack
+                    let Some(frame) = self.call_stack.pop() else {
+                        return Err(VmError::ReturnWithoutCall);
+                    };
+                    self.program_counter = frame.return_address;
+                }
                 Instruction::Stop => {
-                    // println!("Program done, current stack is {:?}", self.stack)
+                    // Halts even if code such as a called subroutine follows
+                    self.program_counter = self.instructions.len();
                 }
             }
         }
+
+        Ok(())
     }
 
-    fn pop_two(&mut self) -> (f64, f64) {
-        let n1 = self.stack.pop();
-        let n2 = self.stack.pop();
+    fn policy_for(&self, op: &BinaryOperator) -> ArithmeticPolicy {
+        self.policies
+            .get(op)
+            .copied()
+            .unwrap_or(ArithmeticPolicy::Error)
+    }
 
-        if n1.is_some() && n2.is_some() {
-            return (n1.unwrap(), n2.unwrap());
-        }
+    // Pushes 1.0 if `test` holds for the top two values (left operand first), else 0.0.
+    // Comparisons follow IEEE 754, so with a NaN operand every comparison is false
+    // except `!=`, and `NaN == NaN` is false too.
+    fn compare(&mut self, test: fn(f64, f64) -> bool) -> Result<(), VmError> {
+        let (n1, n2) = self.pop_two()?;
+        self.stack.push(f64::from(test(n2, n1)));
+        Ok(())
+    }
+
+    fn pop_one(&mut self) -> Result<f64, VmError> {
+        self.stack.pop().ok_or(VmError::StackUnderflow)
+    }
+
+    // Returns (n1, n2) where n1 was on top, so n1 is the right operand and n2 the left
+    fn pop_two(&mut self) -> Result<(f64, f64), VmError> {
+        let n1 = self.pop_one()?;
+        let n2 = self.pop_one()?;
+        Ok((n1, n2))
+    }
+}
+
+// Locals of the active call, or storage outside of one
+fn active_variables<'a>(
+    call_stack: &'a mut [Frame],
+    storage: &'a mut HashMap<String, f64>,
+) -> &'a mut HashMap<String, f64> {
+    match call_stack.last_mut() {
+        Some(frame) => &mut frame.locals,
+        None => storage,
+    }
+}
+
+// Shared by StoreVariable and ReadInput. A variable first stored inside a block
+// expression belongs to it, so it's noted for removal when the block exits.
+fn store(
+    name: &str,
+    val: f64,
+    variables: &mut HashMap<String, f64>,
+    scopes: &mut [Vec<(String, Option<f64>)>],
+    watched: &mut HashMap<String, Vec<f64>>,
+) {
+    if let Some(history) = watched.get_mut(name) {
+        history.push(val);
+    }
+    if let Some(scope) = scopes.last_mut().filter(|_| !variables.contains_key(name)) {
+        scope.push((name.to_string(), None));
+    }
+    variables.insert(name.to_string(), val);
+}
 
-        panic!("Unable to pop two from stack!")
+// One line of input, surrounding whitespace ignored
+fn read_number(reader: &mut dyn BufRead) -> Result<f64, VmError> {
+    let mut line = String::new();
+    match reader.read_line(&mut line) {
+        Ok(0) => Err(VmError::EndOfInput),
+        Ok(_) => line
+            .trim()
+            .parse::<f64>()
+            .map_err(|_| VmError::InvalidInput(line.trim().to_string())),
+        Err(err) => Err(VmError::InvalidInput(err.to_string())),
     }
 }